@@ -0,0 +1,36 @@
+//! Library behind the `keyoxide` CLI.
+//!
+//! Fetches OpenPGP keys and Ariadne Signature Profiles and verifies their claims with doip-rs,
+//! using the same code path as the CLI.
+//!
+//! ```no_run
+//! # async fn run() -> miette::Result<()> {
+//! let profile = keyoxide_cli_rs::verify("hkp:test@doip.rocks").await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod libs;
+
+pub use libs::aspe::AspProfile;
+pub use libs::batch::{Batch, BatchEntry, verify_batch};
+pub use libs::cache::{Cache, CacheMode};
+pub use libs::claim::ClaimVerification;
+pub use libs::config::Config;
+pub use libs::context::VerifyContext;
+pub use libs::discover::{DiscoveryAttempt, DiscoveryMethod, EmailProfile};
+pub use libs::doip::{
+    AppVerificationResult, PrintFormat, Printable, ProofFailure, ProofStatus, VerifiedProof,
+};
+pub use libs::error::AppError;
+pub use libs::keyserver::{FetchedCert, KeyserverStrategy, Keyservers};
+pub use libs::limit::ProofLimiter;
+pub use libs::openpgp::{KeyProfile, ProofSignature, UserIDVerifiedProofs};
+pub use libs::policy::{ExitStatus, RequirePolicy};
+pub use libs::profile::{
+    Profile, Summary, VerifyOptions, verify, verify_profile_uri, verify_with_options,
+};
+pub use libs::retry::RetryPolicy;
+pub use libs::uri::ProfileUri;
+pub use libs::validity::Validity;
+pub use libs::wkd::{WkdLookup, WkdMethod, WkdRequest};
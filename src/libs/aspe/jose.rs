@@ -4,7 +4,7 @@ use josekit::{
     jwt::{self, JwtPayload},
 };
 
-use crate::libs::error::AppError;
+use crate::libs::{error::AppError, retry::RetryPolicy};
 use aspe_rs::aspe_uri::AspeUri;
 use aspe_rs::constants;
 use miette::Result;
@@ -56,7 +56,11 @@ fn extract_jwk_from_jwt(jwt_str: &str) -> Option<Jwk> {
     Jwk::from_map(jwt_header_jwk.to_owned()).ok()
 }
 
-pub async fn fetch_jwt(aspe_uri: &str, skip_verify_ssl: bool) -> Result<String, AppError> {
+pub async fn fetch_jwt(
+    aspe_uri: &str,
+    skip_verify_ssl: bool,
+    retry_policy: &RetryPolicy,
+) -> Result<String, AppError> {
     let aspe_uri: AspeUri =
         AspeUri::from_str(aspe_uri).map_err(|_| AppError::FailedToParseAspeUri)?;
 
@@ -67,13 +71,16 @@ pub async fn fetch_jwt(aspe_uri: &str, skip_verify_ssl: bool) -> Result<String,
         &aspe_uri.local_part
     );
 
-    let res = reqwest::Client::builder()
+    let client = reqwest::Client::builder()
         .danger_accept_invalid_certs(skip_verify_ssl)
         .build()
-        .map_err(AppError::FailedToFetchAspeJWT)?
-        .get(url)
-        .header(reqwest::header::CONTENT_TYPE, constants::JWS_MIME)
-        .send()
+        .map_err(AppError::FailedToFetchAspeJWT)?;
+    let res = retry_policy
+        .send(|| {
+            client
+                .get(&url)
+                .header(reqwest::header::CONTENT_TYPE, constants::JWS_MIME)
+        })
         .await
         .map_err(AppError::FailedToFetchAspeJWT)?;
 
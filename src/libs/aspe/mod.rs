@@ -1,10 +1,16 @@
+use crate::libs::cache::{Cache, CacheKind};
+use crate::libs::context::VerifyContext;
 use crate::libs::doip::verify_proof;
-use crate::libs::{doip::PrintFormat, doip::Profile, doip::VerifiedProof};
+use crate::libs::error::AppError;
+use crate::libs::limit::run_until;
+use crate::libs::retry::RetryPolicy;
+use crate::libs::{doip::PrintFormat, doip::Printable, doip::VerifiedProof};
 use colored::Colorize;
 use colored::customcolors::CustomColor;
 use display_json::{DebugAsJsonPretty, DisplayAsJson};
 use futures::future::join_all;
 use hex_color::HexColor;
+use josekit::jwt::JwtPayload;
 use miette::Result;
 use serde::Serialize;
 
@@ -13,18 +19,18 @@ mod jose;
 
 #[derive(Serialize, DisplayAsJson, DebugAsJsonPretty)]
 pub struct AspProfile {
-    profile_uri: String,
-    version: Option<u64>,
-    name: Option<String>,
-    description: Option<String>,
-    color: Option<String>,
-    verified_proofs: Option<Vec<VerifiedProof>>,
+    pub profile_uri: String,
+    pub version: Option<u64>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub color: Option<String>,
+    pub verified_proofs: Option<Vec<VerifiedProof>>,
 }
 
-impl Profile for AspProfile {
+impl Printable for AspProfile {
     fn print(&self, print_format: &PrintFormat) {
         match print_format {
-            PrintFormat::Json => println!("{self}"),
+            PrintFormat::Json | PrintFormat::Ndjson => println!("{self}"),
             PrintFormat::JsonPretty => println!("{self:?}"),
             PrintFormat::Text => {
                 let hexcolor = HexColor::parse_rgb(&self.color.clone().unwrap()).unwrap();
@@ -63,11 +69,14 @@ impl Profile for AspProfile {
 
                 if let Some(verified_proofs) = self.verified_proofs.as_ref() {
                     for verified_proof in verified_proofs {
-                        if verified_proof.verification_result.is_some() {
-                            print.push_str(format!("    {}: ✅\n", verified_proof.uri).as_str());
-                        } else {
-                            print.push_str(format!("    {}: ❌\n", verified_proof.uri).as_str());
-                        }
+                        print.push_str(
+                            format!(
+                                "    {}: {}\n",
+                                verified_proof.uri,
+                                verified_proof.status_text()
+                            )
+                            .as_str(),
+                        );
                     }
                 }
 
@@ -78,10 +87,68 @@ impl Profile for AspProfile {
 }
 
 impl AspProfile {
-    pub async fn new(profile_uri: &str, skip_verify_ssl: bool) -> Result<Self> {
-        let jwt_unverified_string = fetch_jwt(profile_uri, skip_verify_ssl).await?;
-        let verified_payload =
-            parse_jws_and_generate_verified_asp_profile(&jwt_unverified_string).await?;
+    pub fn proofs(&self) -> impl Iterator<Item = &VerifiedProof> {
+        self.verified_proofs.iter().flatten()
+    }
+
+    /// Fetches the raw JWS of the profile without verifying its claims, served from `cache`
+    /// while it is fresh and only from `cache` when offline. Only JWS with a valid signature
+    /// are cached
+    pub async fn fetch_jws(
+        profile_uri: &str,
+        skip_verify_ssl: bool,
+        retry_policy: &RetryPolicy,
+        cache: &Cache,
+    ) -> Result<String> {
+        let (jws, _) =
+            Self::fetch_verified_jws(profile_uri, skip_verify_ssl, retry_policy, cache).await?;
+        Ok(jws)
+    }
+
+    /// Same as [`AspProfile::fetch_jws`], along with the payload of the JWS once its signature
+    /// is verified
+    async fn fetch_verified_jws(
+        profile_uri: &str,
+        skip_verify_ssl: bool,
+        retry_policy: &RetryPolicy,
+        cache: &Cache,
+    ) -> Result<(String, Result<JwtPayload, AppError>)> {
+        if let Some(jws) = cache.get::<String>(CacheKind::Jws, profile_uri) {
+            let verified_payload = parse_jws_and_generate_verified_asp_profile(&jws).await;
+            return Ok((jws, verified_payload));
+        }
+        if cache.is_offline() {
+            return Err(AppError::NotAvailableOffline {
+                uri: profile_uri.to_string(),
+            }
+            .into());
+        }
+
+        let jws = fetch_jwt(profile_uri, skip_verify_ssl, retry_policy).await?;
+        let verified_payload = parse_jws_and_generate_verified_asp_profile(&jws).await;
+        if verified_payload.is_ok() {
+            cache.put(CacheKind::Jws, profile_uri, &jws);
+        }
+        Ok((jws, verified_payload))
+    }
+
+    pub async fn new(
+        profile_uri: &str,
+        skip_verify_ssl: bool,
+        context: &VerifyContext,
+    ) -> Result<Self> {
+        let (_, verified_payload) = run_until(
+            context.limiter.deadline(),
+            Self::fetch_verified_jws(
+                profile_uri,
+                skip_verify_ssl,
+                &context.retry_policy,
+                &context.cache,
+            ),
+        )
+        .await
+        .ok_or(AppError::TotalTimeoutElapsed)??;
+        let verified_payload = verified_payload?;
 
         let version: Option<u64> = verified_payload
             .claim("http://ariadne.id/version")
@@ -108,15 +175,11 @@ impl AspProfile {
 
                 for claim_uri in claim_uris.into_iter().flatten() {
                     let verification_result =
-                        verify_proof(claim_uri.clone(), profile_uri.to_string());
+                        verify_proof(claim_uri.clone(), profile_uri.to_string(), context);
                     proofs_futures.push(verification_result)
                 }
 
-                let proofs = join_all(proofs_futures)
-                    .await
-                    .into_iter()
-                    .map(|(s, r)| VerifiedProof::new(s, r))
-                    .collect();
+                let proofs = join_all(proofs_futures).await;
 
                 Some(proofs)
             }
@@ -137,12 +200,28 @@ impl AspProfile {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::libs::context::cassette_context;
+    use crate::libs::doip::ProofStatus;
 
+    /// The JWS is recorded from `make start-test-aspe-server`, which serves
+    /// `__tests__/aspe` with a self-signed cert. Proof results aren't recorded as they come
+    /// from the live services, so every proof is replayed as unverifiable offline
     #[tokio::test]
     async fn aspe() {
-        let asp_profile = AspProfile::new(&"aspe:keyoxide.org:TOICV3SYXNJP7E4P5AOK5DHW44", false)
-            .await
-            .unwrap();
+        let context = cassette_context();
+        let asp_profile =
+            AspProfile::new("aspe:localhost:TOICV3SYXNJP7E4P5AOK5DHW44", true, &context)
+                .await
+                .unwrap();
         asp_profile.print(&PrintFormat::Text);
+
+        assert_eq!(asp_profile.name.as_deref(), Some("Yarmo"));
+        let verified_proofs = asp_profile.verified_proofs.unwrap();
+        assert_eq!(verified_proofs.len(), 6);
+        if context.cache.is_offline() {
+            for verified_proof in verified_proofs {
+                assert_eq!(verified_proof.status, ProofStatus::UnverifiableOffline);
+            }
+        }
     }
 }
@@ -0,0 +1,172 @@
+use std::fs;
+
+use super::{
+    doip::{PrintFormat, Printable},
+    error::AppError,
+    policy::{ExitStatus, RequirePolicy},
+    profile::{Profile, VerifyOptions, verify_with_options},
+};
+use display_json::{DebugAsJsonPretty, DisplayAsJson};
+use futures::{Stream, StreamExt, stream};
+use serde::Serialize;
+
+/// Result of verifying one uri of a batch
+#[derive(Serialize, DisplayAsJson, DebugAsJsonPretty)]
+pub struct BatchEntry {
+    pub uri: String,
+    pub profile: Option<Profile>,
+    pub error: Option<String>,
+    #[serde(skip)]
+    error_exit_status: Option<ExitStatus>,
+}
+
+impl BatchEntry {
+    pub async fn verify(uri: String, options: &VerifyOptions) -> Self {
+        match verify_with_options(&uri, options).await {
+            Ok(profile) => BatchEntry {
+                uri,
+                profile: Some(profile),
+                error: None,
+                error_exit_status: None,
+            },
+            Err(report) => BatchEntry {
+                uri,
+                profile: None,
+                error: Some(report.to_string()),
+                error_exit_status: Some(ExitStatus::from_error(&report)),
+            },
+        }
+    }
+
+    pub fn exit_status(&self, policy: &RequirePolicy) -> ExitStatus {
+        match (&self.profile, self.error_exit_status) {
+            (_, Some(error_exit_status)) => error_exit_status,
+            (Some(profile), None) => ExitStatus::evaluate(profile.proofs(), policy),
+            (None, None) => ExitStatus::FetchFailed,
+        }
+    }
+}
+
+impl Printable for BatchEntry {
+    fn print(&self, print_format: &PrintFormat) {
+        match print_format {
+            PrintFormat::Json | PrintFormat::Ndjson => println!("{self}"),
+            PrintFormat::JsonPretty => println!("{self:?}"),
+            PrintFormat::Text => {
+                println!("==> {}", self.uri);
+                match (&self.profile, &self.error) {
+                    (Some(profile), _) => profile.print(print_format),
+                    (None, Some(error)) => println!("Error: {error}"),
+                    (None, None) => {}
+                }
+            }
+        }
+    }
+}
+
+/// Every entry of a batch, printed as a single JSON document
+#[derive(Serialize, DisplayAsJson, DebugAsJsonPretty)]
+pub struct Batch {
+    pub entries: Vec<BatchEntry>,
+}
+
+impl Printable for Batch {
+    fn print(&self, print_format: &PrintFormat) {
+        match print_format {
+            PrintFormat::Json => println!("{self}"),
+            PrintFormat::JsonPretty => println!("{self:?}"),
+            PrintFormat::Ndjson | PrintFormat::Text => {
+                for entry in &self.entries {
+                    entry.print(print_format);
+                }
+            }
+        }
+    }
+}
+
+impl Batch {
+    /// Combines the exit status of every entry, errors take precedence over failed proofs
+    pub fn exit_status(&self, policy: &RequirePolicy) -> ExitStatus {
+        let exit_statuses: Vec<ExitStatus> = self
+            .entries
+            .iter()
+            .map(|entry| entry.exit_status(policy))
+            .collect();
+        combine_exit_statuses(&exit_statuses)
+    }
+}
+
+pub fn combine_exit_statuses(exit_statuses: &[ExitStatus]) -> ExitStatus {
+    let errored = exit_statuses.iter().find(|exit_status| {
+        matches!(
+            exit_status,
+            ExitStatus::MalformedInput | ExitStatus::FetchFailed
+        )
+    });
+    if let Some(errored) = errored {
+        return *errored;
+    }
+
+    if exit_statuses.iter().all(|s| *s == ExitStatus::Verified) {
+        ExitStatus::Verified
+    } else if exit_statuses.iter().all(|s| *s == ExitStatus::NoneVerified) {
+        ExitStatus::NoneVerified
+    } else {
+        ExitStatus::SomeFailed
+    }
+}
+
+/// Verifies every uri with at most `jobs` profiles in flight, yielding entries in input order
+pub fn verify_batch(
+    uris: Vec<String>,
+    options: &VerifyOptions,
+    jobs: usize,
+) -> impl Stream<Item = BatchEntry> + '_ {
+    stream::iter(uris)
+        .map(move |uri| BatchEntry::verify(uri, options))
+        .buffered(jobs.max(1))
+}
+
+/// Reads one uri per line, skipping blank lines and `#` comments
+pub fn read_batch_file(batch_path: &str) -> Result<Vec<String>, AppError> {
+    let contents = fs::read_to_string(batch_path).map_err(AppError::FailedToReadBatchFile)?;
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_batch_file_skips_comments() {
+        let uris = read_batch_file("__tests__/data/BATCH_PROFILE_URIS.txt").unwrap();
+        assert_eq!(
+            uris,
+            [
+                "hkp:test@doip.rocks",
+                "wkd:alexis.lowe@chimbosonic.com",
+                "aspe:keyoxide.org:TOICV3SYXNJP7E4P5AOK5DHW44"
+            ]
+        );
+    }
+
+    #[test]
+    fn combine_batch_exit_statuses() {
+        use ExitStatus::*;
+        assert_eq!(combine_exit_statuses(&[Verified, Verified]), Verified);
+        assert_eq!(combine_exit_statuses(&[Verified, NoneVerified]), SomeFailed);
+        assert_eq!(
+            combine_exit_statuses(&[NoneVerified, NoneVerified]),
+            NoneVerified
+        );
+        assert_eq!(
+            combine_exit_statuses(&[Verified, FetchFailed, MalformedInput]),
+            FetchFailed
+        );
+    }
+}
@@ -0,0 +1,398 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    doip::{PrintFormat, Printable},
+    error::AppError,
+};
+use display_json::{DebugAsJsonPretty, DisplayAsJson};
+use sequoia_openpgp::{crypto::hash::Digest, types::HashAlgorithm};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+/// What a cache entry holds, each kind lives in its own directory and expires on its own
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheKind {
+    /// ASCII-Armored certs fetched for a profile uri
+    Cert,
+    /// Raw JWS of an Ariadne Signature Profile
+    Jws,
+    /// Verified or not verified proof results, errors are never cached
+    Proof,
+    /// Verified or not verified results of `keyoxide claim`, with the providers matched
+    Claim,
+}
+
+impl CacheKind {
+    pub const ALL: [CacheKind; 4] = [
+        CacheKind::Cert,
+        CacheKind::Jws,
+        CacheKind::Proof,
+        CacheKind::Claim,
+    ];
+
+    fn dir_name(&self) -> &'static str {
+        match self {
+            CacheKind::Cert => "certs",
+            CacheKind::Jws => "jws",
+            CacheKind::Proof => "proofs",
+            CacheKind::Claim => "claims",
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        match self {
+            CacheKind::Cert | CacheKind::Jws => Duration::from_secs(24 * 60 * 60),
+            CacheKind::Proof | CacheKind::Claim => Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// How a run uses the cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Entries are read while they are fresh and written after every fetch
+    Use,
+    /// Entries are never read but still written, `--refresh`
+    Refresh,
+    /// The cache is neither read nor written, `--no-cache`
+    #[default]
+    Disabled,
+    /// Entries are read however old they are and nothing is fetched, `--offline`
+    Offline,
+    /// Entries never expire, missing ones are fetched and written, used to replay cassettes
+    Pinned,
+}
+
+/// On-disk cache under `$XDG_CACHE_HOME/keyoxide`, falling back to `~/.cache/keyoxide`.
+/// Reads and writes are best effort, a broken cache only costs a fetch
+#[derive(Debug, Clone, Default)]
+pub struct Cache {
+    dir: Option<PathBuf>,
+    mode: CacheMode,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredEntry<T> {
+    key: String,
+    stored_at: u64,
+    value: T,
+}
+
+/// A cache entry as shown by `keyoxide cache list`
+#[derive(Serialize, DisplayAsJson, DebugAsJsonPretty)]
+pub struct CacheEntry {
+    pub kind: CacheKind,
+    pub key: String,
+    /// Seconds since the entry was stored
+    pub age: u64,
+    pub expired: bool,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl Printable for CacheEntry {
+    fn print(&self, print_format: &PrintFormat) {
+        match print_format {
+            PrintFormat::Json | PrintFormat::Ndjson => println!("{self}"),
+            PrintFormat::JsonPretty => println!("{self:?}"),
+            PrintFormat::Text => println!(
+                "{:?} {} {}s{}",
+                self.kind,
+                self.key,
+                self.age,
+                if self.expired { " (expired)" } else { "" }
+            ),
+        }
+    }
+}
+
+impl Cache {
+    pub fn new(mode: CacheMode) -> Self {
+        Cache {
+            dir: default_cache_dir(),
+            mode,
+        }
+    }
+
+    /// Cache stored in `dir` instead of the XDG cache directory
+    pub fn in_dir(dir: impl Into<PathBuf>, mode: CacheMode) -> Self {
+        Cache {
+            dir: Some(dir.into()),
+            mode,
+        }
+    }
+
+    /// Checked-in cache used by tests to record and replay keys and ASP JWS.
+    /// `KEYOXIDE_CASSETTE` picks how: `replay` (default) never touches the network, `once`
+    /// replays recorded entries and records missing ones and `record` records everything again
+    pub fn cassette(dir: impl Into<PathBuf>) -> Self {
+        let mode = match env::var("KEYOXIDE_CASSETTE").as_deref() {
+            Ok("record") => CacheMode::Refresh,
+            Ok("once") => CacheMode::Pinned,
+            _ => CacheMode::Offline,
+        };
+        Cache::in_dir(dir, mode)
+    }
+
+    /// Whether nothing may be fetched and everything must come from the cache
+    pub fn is_offline(&self) -> bool {
+        self.mode == CacheMode::Offline
+    }
+
+    /// Value stored for `key` if it hasn't expired and the cache may be read, expired entries
+    /// are still used offline as they are all there is
+    pub fn get<T: DeserializeOwned>(&self, kind: CacheKind, key: &str) -> Option<T> {
+        if !matches!(
+            self.mode,
+            CacheMode::Use | CacheMode::Offline | CacheMode::Pinned
+        ) {
+            return None;
+        }
+        let path = self.entry_path(kind, key)?;
+        let stored_entry: StoredEntry<T> = serde_json::from_slice(&fs::read(path).ok()?).ok()?;
+        let never_expires = matches!(self.mode, CacheMode::Offline | CacheMode::Pinned);
+        match never_expires || age(stored_entry.stored_at) <= kind.ttl().as_secs() {
+            true => Some(stored_entry.value),
+            false => None,
+        }
+    }
+
+    /// Stores `value` for `key` unless the cache is disabled or offline
+    pub fn put<T: Serialize>(&self, kind: CacheKind, key: &str, value: &T) {
+        if matches!(self.mode, CacheMode::Disabled | CacheMode::Offline) {
+            return;
+        }
+        let Some(path) = self.entry_path(kind, key) else {
+            return;
+        };
+        let stored_entry = StoredEntry {
+            key: key.to_string(),
+            stored_at: now(),
+            value,
+        };
+        if let (Some(parent), Ok(json)) = (path.parent(), serde_json::to_vec(&stored_entry)) {
+            let _ = fs::create_dir_all(parent).and_then(|_| fs::write(&path, json));
+        }
+    }
+
+    /// Every entry of the cache, expired or not
+    pub fn list(&self) -> Result<Vec<CacheEntry>, AppError> {
+        let dir = self.dir()?;
+        let mut entries = Vec::new();
+        for kind in CacheKind::ALL {
+            let kind_dir = dir.join(kind.dir_name());
+            let Ok(read_dir) = fs::read_dir(&kind_dir) else {
+                continue;
+            };
+            for dir_entry in read_dir {
+                let path = dir_entry.map_err(AppError::FailedToAccessCache)?.path();
+                entries.push(read_entry(kind, path));
+            }
+        }
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(entries)
+    }
+
+    /// Removes every entry, returns how many were removed. Only the entry files are removed as
+    /// `--cache-dir` may point at a directory holding more than the cache
+    pub fn clear(&self) -> Result<usize, AppError> {
+        let entries = self.list()?;
+        for entry in &entries {
+            fs::remove_file(&entry.path).map_err(AppError::FailedToAccessCache)?;
+        }
+        let dir = self.dir()?;
+        for kind in CacheKind::ALL {
+            // Fails when something else was put in there, which is then left alone
+            let _ = fs::remove_dir(dir.join(kind.dir_name()));
+        }
+        Ok(entries.len())
+    }
+
+    /// Removes every expired or unreadable entry, returns how many were removed
+    pub fn prune(&self) -> Result<usize, AppError> {
+        let mut removed = 0;
+        for entry in self.list()?.into_iter().filter(|entry| entry.expired) {
+            fs::remove_file(&entry.path).map_err(AppError::FailedToAccessCache)?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+
+    fn dir(&self) -> Result<&Path, AppError> {
+        self.dir.as_deref().ok_or_else(|| {
+            AppError::FailedToAccessCache(io::Error::new(
+                io::ErrorKind::NotFound,
+                "neither XDG_CACHE_HOME nor HOME is set",
+            ))
+        })
+    }
+
+    fn entry_path(&self, kind: CacheKind, key: &str) -> Option<PathBuf> {
+        Some(
+            self.dir
+                .as_ref()?
+                .join(kind.dir_name())
+                .join(entry_file_name(key)?),
+        )
+    }
+}
+
+fn default_cache_dir() -> Option<PathBuf> {
+    let cache_home = match env::var_os("XDG_CACHE_HOME") {
+        Some(cache_home) if !cache_home.is_empty() => PathBuf::from(cache_home),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+    Some(cache_home.join("keyoxide"))
+}
+
+/// Entries that can't be read are reported as expired so `prune` removes them
+fn read_entry(kind: CacheKind, path: PathBuf) -> CacheEntry {
+    let stored_entry = fs::read(&path)
+        .ok()
+        .and_then(|json| serde_json::from_slice::<StoredEntry<serde_json::Value>>(&json).ok());
+    match stored_entry {
+        Some(stored_entry) => CacheEntry {
+            kind,
+            key: stored_entry.key,
+            age: age(stored_entry.stored_at),
+            expired: age(stored_entry.stored_at) > kind.ttl().as_secs(),
+            path,
+        },
+        None => CacheEntry {
+            kind,
+            key: path.display().to_string(),
+            age: 0,
+            expired: true,
+            path,
+        },
+    }
+}
+
+/// Hex SHA-256 of the key, so any key maps to a short, valid file name
+fn entry_file_name(key: &str) -> Option<String> {
+    let mut context = HashAlgorithm::SHA256.context().ok()?;
+    context.update(key.as_bytes());
+    let digest = context.into_digest().ok()?;
+    let mut file_name: String = digest.iter().map(|byte| format!("{byte:02x}")).collect();
+    file_name.push_str(".json");
+    Some(file_name)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs())
+}
+
+fn age(stored_at: u64) -> u64 {
+    now().saturating_sub(stored_at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_cache(name: &str, mode: CacheMode) -> Cache {
+        let dir = env::temp_dir().join(format!("keyoxide-cache-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        Cache::in_dir(dir, mode)
+    }
+
+    #[test]
+    fn entry_file_names() {
+        assert_eq!(
+            entry_file_name("hkp:test@doip.rocks").as_deref(),
+            Some("bf643cb2f4a0321d52226c08fa3ee92fc69278efa8459693a6a9fc1a718103da.json")
+        );
+    }
+
+    #[test]
+    fn cache_round_trip() {
+        let cache = test_cache("round-trip", CacheMode::Use);
+        cache.put(CacheKind::Jws, "aspe:doip.rocks:ABC", &"jws".to_string());
+        assert_eq!(
+            cache.get::<String>(CacheKind::Jws, "aspe:doip.rocks:ABC"),
+            Some("jws".to_string())
+        );
+        assert_eq!(
+            cache.get::<String>(CacheKind::Cert, "aspe:doip.rocks:ABC"),
+            None
+        );
+
+        let entries = cache.list().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].key, "aspe:doip.rocks:ABC");
+        assert_eq!(cache.prune().unwrap(), 0);
+        assert_eq!(cache.clear().unwrap(), 1);
+        assert!(cache.list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn clear_only_removes_entries() {
+        let cache = test_cache("clear", CacheMode::Use);
+        cache.put(CacheKind::Cert, "key", &"cert".to_string());
+        let dir = cache.dir().unwrap().to_path_buf();
+        fs::write(dir.join("unrelated"), "kept").unwrap();
+
+        assert_eq!(cache.clear().unwrap(), 1);
+        assert!(dir.join("unrelated").exists());
+        assert!(!dir.join("certs").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn refresh_writes_without_reading() {
+        let cache = test_cache("refresh", CacheMode::Refresh);
+        cache.put(CacheKind::Jws, "key", &"jws".to_string());
+        assert_eq!(cache.get::<String>(CacheKind::Jws, "key"), None);
+        assert_eq!(cache.list().unwrap().len(), 1);
+        cache.clear().unwrap();
+    }
+
+    #[test]
+    fn pinned_entries_never_expire() {
+        let cache = test_cache("pinned", CacheMode::Pinned);
+        let stored_entry = StoredEntry {
+            key: "key".to_string(),
+            stored_at: 0,
+            value: "jws",
+        };
+        let path = cache.entry_path(CacheKind::Jws, "key").unwrap();
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, serde_json::to_vec(&stored_entry).unwrap()).unwrap();
+
+        assert_eq!(
+            cache.get::<String>(CacheKind::Jws, "key"),
+            Some("jws".to_string())
+        );
+        let cache = Cache {
+            mode: CacheMode::Use,
+            ..cache
+        };
+        assert_eq!(cache.get::<String>(CacheKind::Jws, "key"), None);
+        cache.clear().unwrap();
+    }
+
+    #[test]
+    fn offline_reads_without_writing() {
+        let dir = test_cache("offline", CacheMode::Use);
+        dir.put(CacheKind::Jws, "key", &"jws".to_string());
+
+        let cache = Cache {
+            mode: CacheMode::Offline,
+            ..dir.clone()
+        };
+        assert!(cache.is_offline());
+        assert_eq!(
+            cache.get::<String>(CacheKind::Jws, "key"),
+            Some("jws".to_string())
+        );
+        cache.put(CacheKind::Jws, "other", &"jws".to_string());
+        assert_eq!(cache.get::<String>(CacheKind::Jws, "other"), None);
+        dir.clear().unwrap();
+    }
+}
@@ -0,0 +1,185 @@
+use super::{
+    cache::CacheKind,
+    context::VerifyContext,
+    doip::{
+        AppVerificationResult, MatchedServiceProvider, PrintFormat, Printable, ProofFailure,
+        ProofStatus, fetch_and_verify_proof,
+    },
+};
+use display_json::{DebugAsJsonPretty, DisplayAsJson};
+use serde::{Deserialize, Serialize};
+
+/// Outcome of verifying a single claim, with the details needed to debug it
+#[derive(Serialize, Deserialize, DisplayAsJson, DebugAsJsonPretty)]
+pub struct ClaimVerification {
+    pub service_uri: String,
+    pub identity_uri: String,
+    pub status: ProofStatus,
+    pub matched_service_providers: Vec<MatchedServiceProvider>,
+    pub verification_result: Option<AppVerificationResult>,
+    pub error: Option<ProofFailure>,
+    /// Number of times the proof was fetched, more than 1 when transient failures were retried
+    #[serde(default)]
+    pub attempts: u32,
+}
+
+impl ClaimVerification {
+    /// Matches `service_uri` against the known service providers and verifies that the proof
+    /// found there references `identity_uri`, reusing a fresh cached result when there is one.
+    /// Claims are verified like proofs, bound by the limiter, retries and timeouts of `context`,
+    /// and only verified and not verified results are cached
+    pub async fn new(service_uri: &str, identity_uri: &str, context: &VerifyContext) -> Self {
+        let cache = &context.cache;
+        let cache_key = format!("{service_uri} {identity_uri}");
+        if let Some(mut claim_verification) =
+            cache.get::<ClaimVerification>(CacheKind::Claim, &cache_key)
+        {
+            // Nothing was fetched this time
+            claim_verification.attempts = 0;
+            return claim_verification;
+        }
+        if cache.is_offline() {
+            return ClaimVerification {
+                service_uri: service_uri.to_string(),
+                identity_uri: identity_uri.to_string(),
+                status: ProofStatus::UnverifiableOffline,
+                matched_service_providers: Vec::new(),
+                verification_result: None,
+                error: None,
+                attempts: 0,
+            };
+        }
+
+        let proof_verification = fetch_and_verify_proof(service_uri, identity_uri, context).await;
+        let matched_service_providers = proof_verification.matched_service_providers.clone();
+        let attempts = proof_verification.attempts;
+        let (status, verification_result, error) = proof_verification.outcome();
+        let claim_verification = ClaimVerification {
+            service_uri: service_uri.to_string(),
+            identity_uri: identity_uri.to_string(),
+            status,
+            matched_service_providers,
+            verification_result,
+            error,
+            attempts,
+        };
+        if matches!(
+            claim_verification.status,
+            ProofStatus::Verified | ProofStatus::NotVerified
+        ) {
+            cache.put(CacheKind::Claim, &cache_key, &claim_verification);
+        }
+        claim_verification
+    }
+
+    pub fn is_verified(&self) -> bool {
+        self.status == ProofStatus::Verified
+    }
+}
+
+impl Printable for ClaimVerification {
+    fn print(&self, print_format: &PrintFormat) {
+        match print_format {
+            PrintFormat::Json | PrintFormat::Ndjson => println!("{self}"),
+            PrintFormat::JsonPretty => println!("{self:?}"),
+            PrintFormat::Text => {
+                let mut print = String::new();
+                print.push_str(format!("Claim: {}\n", self.service_uri).as_str());
+                print.push_str(format!("  Identity: {}\n", self.identity_uri).as_str());
+
+                print.push_str("  Matched Service Providers:\n");
+                if self.matched_service_providers.is_empty() {
+                    print.push_str("    none\n");
+                }
+                for service_provider in &self.matched_service_providers {
+                    print.push_str(format!("    {}\n", service_provider.name).as_str());
+                    print.push_str(
+                        format!("      Proof: {}\n", service_provider.proof_location).as_str(),
+                    );
+                }
+
+                print.push_str(format!("  Result: {}\n", self.status.symbol()).as_str());
+                if self.attempts > 1 {
+                    print.push_str(format!("  Attempts: {}\n", self.attempts).as_str());
+                }
+                if let Some(proxy_used) = self
+                    .verification_result
+                    .as_ref()
+                    .and_then(|v| v.proxy_used.as_ref())
+                {
+                    print.push_str(format!("  Proxy: {}\n", proxy_used).as_str());
+                }
+                if let Some(error) = &self.error {
+                    print.push_str(
+                        format!("  Error: {}: {}\n", error.kind.name(), error.message).as_str(),
+                    );
+                    if let Some(http_status) = error.http_status {
+                        print.push_str(format!("  HTTP status: {http_status}\n").as_str());
+                    }
+                    if error.proxy_attempted {
+                        print.push_str("  Failed through the proxy\n");
+                    }
+                }
+
+                print!("{}", print);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::cache::{Cache, CacheMode};
+
+    /// Claim results can't be replayed without recording them from the live service first,
+    /// run with `cargo test -- --ignored`
+    #[tokio::test]
+    #[ignore = "fetches the proof from fosstodon.org"]
+    async fn claim() {
+        let claim_verification = ClaimVerification::new(
+            "https://fosstodon.org/@chimbosonic",
+            "openpgp4fpr:AC48BC1F029B6188D97E2D807C855DB4466DF0C6",
+            &VerifyContext::default(),
+        )
+        .await;
+        assert!(claim_verification.is_verified());
+        assert_eq!(claim_verification.matched_service_providers.len(), 1);
+        let service_provider = &claim_verification.matched_service_providers[0];
+        assert_eq!(service_provider.id, "activitypub");
+        assert_eq!(
+            service_provider.proof_location,
+            "https://fosstodon.org/@chimbosonic"
+        );
+        assert!(claim_verification.error.is_none());
+        claim_verification.print(&PrintFormat::Text);
+    }
+
+    #[tokio::test]
+    async fn claims_matching_no_provider_are_not_fetched() {
+        let claim_verification = ClaimVerification::new(
+            "nothing:matches-this",
+            "openpgp4fpr:AC48BC1F029B6188D97E2D807C855DB4466DF0C6",
+            &VerifyContext::default(),
+        )
+        .await;
+        assert!(!claim_verification.is_verified());
+        assert!(claim_verification.matched_service_providers.is_empty());
+        assert_eq!(claim_verification.attempts, 0);
+    }
+
+    #[tokio::test]
+    async fn offline_claims_that_are_not_cached_are_unverifiable() {
+        let claim_verification = ClaimVerification::new(
+            "https://fosstodon.org/@nobody",
+            "openpgp4fpr:AC48BC1F029B6188D97E2D807C855DB4466DF0C6",
+            &VerifyContext {
+                cache: Cache::in_dir("__tests__/cassettes", CacheMode::Offline),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(claim_verification.status, ProofStatus::UnverifiableOffline);
+        assert!(claim_verification.matched_service_providers.is_empty());
+    }
+}
@@ -1,30 +1,434 @@
-use super::doip::PrintFormat;
+use std::{
+    env,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use super::{
+    batch::read_batch_file,
+    cache::{Cache, CacheMode},
+    config::Config,
+    context::VerifyContext,
+    doip::PrintFormat,
+    error::AppError,
+    keyserver::{KeyserverStrategy, Keyservers},
+    limit::{
+        DEFAULT_MAX_CONCURRENT_PROOFS, DEFAULT_PER_HOST_RATE, DEFAULT_PROOF_TIMEOUT_SECS,
+        ProofLimiter,
+    },
+    policy::RequirePolicy,
+    retry::{DEFAULT_RETRIES, RetryPolicy},
+    uri::ProfileUri,
+};
+use chrono::{DateTime, Utc};
+
+const EXIT_CODES_HELP: &str = "Exit codes:
+  0  Verified, the `--require` policy is satisfied
+  1  Some proofs failed and the `--require` policy is not satisfied
+  2  Malformed input
+  3  No proof was verified
+  4  Failed to fetch or process the profile";
 
 #[derive(clap::Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, after_help = EXIT_CODES_HELP)]
 pub struct Args {
-    /// Uri for looking up a key can be (hkp:<email_address> || hkp:<key_fingerprint> || wkd:<email_address>)
-    #[arg(short, long, required_unless_present_any(["input_key_file"]))]
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub legacy: LegacyArgs,
+
+    #[command(flatten)]
+    pub global: GlobalArgs,
+
+    #[arg(skip)]
+    pub config: Config,
+}
+
+impl Args {
+    /// Parses the command line, taking the default of every flag that isn't passed or set
+    /// through its env var from the config file
+    pub fn parse_with_config() -> Result<Self, AppError> {
+        let config = Config::load(config_path().as_deref())?;
+        let command = config.defaults().iter().fold(
+            <Args as ::clap::CommandFactory>::command(),
+            |command, (id, value)| with_default(command, id, value, true),
+        );
+        let mut args = <Args as ::clap::FromArgMatches>::from_arg_matches(&command.get_matches())
+            .unwrap_or_else(|error| error.exit());
+        args.config = config;
+        Ok(args)
+    }
+}
+
+/// `--config` has to be known before the command line is parsed, as the config file sets the
+/// defaults it is parsed with. The command line is parsed a first time ignoring errors, which
+/// the second parse reports
+fn config_path() -> Option<String> {
+    let matches = match <Args as ::clap::CommandFactory>::command()
+        .ignore_errors(true)
+        .try_get_matches()
+    {
+        Ok(matches) => matches,
+        Err(_) => return env::var("KEYOXIDE_CONFIG").ok(),
+    };
+    let mut matches = &matches;
+    loop {
+        if let Ok(Some(path)) = matches.try_get_one::<String>("config") {
+            return Some(path.clone());
+        }
+        matches = matches.subcommand()?.1;
+    }
+}
+
+/// Sets the default of flag `id` on `command` and its subcommands. Deprecated top-level flags
+/// are left alone so a config doesn't turn on the legacy invocation
+fn with_default(command: ::clap::Command, id: &str, value: &str, is_root: bool) -> ::clap::Command {
+    let has_arg = command
+        .get_arguments()
+        .any(|arg| arg.get_id() == id && (!is_root || arg.is_global_set()));
+    let command = match has_arg {
+        true => command.mut_arg(id, |arg| arg.default_value(value.to_string())),
+        false => command,
+    };
+
+    let subcommand_names: Vec<String> = command
+        .get_subcommands()
+        .map(|subcommand| subcommand.get_name().to_string())
+        .collect();
+    subcommand_names.iter().fold(command, |command, name| {
+        command.mut_subcommand(name, |subcommand| {
+            with_default(subcommand, id, value, false)
+        })
+    })
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// Fetch one or more profiles and verify all of their claims
+    Verify(VerifyArgs),
+
+    /// Fetch a key and print it ASCII-Armored without verifying anything
+    Fetch(FetchArgs),
+
+    /// Show the subkeys, UserIDs, certifications and claims of a key without verifying them
+    Inspect(KeyArgs),
+
+    /// Verify a single claim and show how it was matched and fetched
+    Claim(ClaimArgs),
+
+    /// Work with Ariadne Signature Profiles
+    #[command(subcommand)]
+    Aspe(AspeCommand),
+
+    /// Manage the cache of fetched keys, ASP JWS, proof and claim results
+    #[command(subcommand)]
+    Cache(CacheCommand),
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum AspeCommand {
+    /// Fetch an ASP and verify all of its claims
+    Verify(AspeArgs),
+
+    /// Fetch an ASP and print the raw JWS without verifying anything
+    Fetch(AspeArgs),
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum CacheCommand {
+    /// List every cache entry with its age
+    List,
+
+    /// Remove every cache entry
+    Clear,
+
+    /// Remove expired cache entries
+    Prune,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct VerifyArgs {
+    /// Uris for looking up profiles can be (hkp(s):<email_address> || hkp(s):<key_fingerprint> || hkp(s):<key_id> || wkd:<email_address> || email:<email_address> || aspe:<domain>:<fingerprint> || openpgp4fpr:<key_fingerprint> || file:<path> || https://<url>)
+    #[arg(required_unless_present_any(["input_key_file", "batch"]))]
+    pub doip_profile_uris: Vec<String>,
+
+    /// Domain name of keyserver, or name of a keyserver list from the config, used for hkp lookup. Repeat or separate with commas to query several keyservers in order. if not provided will default to keys.openpgp.org
+    #[arg(
+        short,
+        long,
+        required(false),
+        value_delimiter = ',',
+        env = "KEYOXIDE_KEYSERVER_DOMAIN"
+    )]
+    pub keyserver_domain: Vec<String>,
+
+    /// Path to file containing an ASCII-Armored or binary Public Key, `-` reads from stdin
+    #[arg(short, long, required(false))]
+    pub input_key_file: Option<String>,
+
+    /// Path to file containing one profile uri per line, blank lines and `#` comments are skipped
+    #[arg(short, long, required(false))]
+    pub batch: Option<String>,
+
+    /// Maximum number of profiles verified at the same time
+    #[arg(short, long, default_value_t = 8, env = "KEYOXIDE_JOBS")]
+    pub jobs: usize,
+}
+
+impl VerifyArgs {
+    /// Every profile uri to verify, `-i <INPUT_KEY_FILE>` is turned into a `file:` uri
+    pub fn profile_uris(&self) -> Result<Vec<String>, AppError> {
+        let mut profile_uris = self.doip_profile_uris.clone();
+        if let Some(key_path) = &self.input_key_file {
+            profile_uris.push(ProfileUri::File(key_path.to_string()).to_string());
+        }
+        if let Some(batch_path) = &self.batch {
+            profile_uris.extend(read_batch_file(batch_path)?);
+        }
+
+        match profile_uris.is_empty() {
+            true => Err(AppError::ProfileNotProvided),
+            false => Ok(profile_uris),
+        }
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct KeyArgs {
+    /// Uri for looking up a profile can be (hkp(s):<email_address> || hkp(s):<key_fingerprint> || hkp(s):<key_id> || wkd:<email_address> || email:<email_address> || aspe:<domain>:<fingerprint> || openpgp4fpr:<key_fingerprint> || file:<path> || https://<url>)
+    #[arg(required_unless_present_any(["input_key_file"]))]
+    pub doip_profile_uri: Option<String>,
+
+    /// Domain name of keyserver, or name of a keyserver list from the config, used for hkp lookup. Repeat or separate with commas to query several keyservers in order. if not provided will default to keys.openpgp.org
+    #[arg(
+        short,
+        long,
+        required(false),
+        value_delimiter = ',',
+        env = "KEYOXIDE_KEYSERVER_DOMAIN"
+    )]
+    pub keyserver_domain: Vec<String>,
+
+    /// Path to file containing an ASCII-Armored or binary Public Key, `-` reads from stdin
+    #[arg(short, long, required(false), conflicts_with("doip_profile_uri"))]
+    pub input_key_file: Option<String>,
+}
+
+impl KeyArgs {
+    /// Parses `<DOIP_PROFILE_URI>` or turns `-i <INPUT_KEY_FILE>` into a `file:` uri, `file:-` is stdin
+    pub fn profile_uri(&self) -> Result<ProfileUri, AppError> {
+        match (&self.doip_profile_uri, &self.input_key_file) {
+            (Some(doip_profile_uri), _) => doip_profile_uri.parse(),
+            (None, Some(key_path)) => Ok(ProfileUri::File(key_path.to_string())),
+            (None, None) => Err(AppError::ProfileNotProvided),
+        }
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct FetchArgs {
+    /// Uri for looking up a key can be (hkp(s):<email_address> || hkp(s):<key_fingerprint> || hkp(s):<key_id> || wkd:<email_address> || email:<email_address> || openpgp4fpr:<key_fingerprint> || file:<path> || https://<url>)
+    pub doip_profile_uri: String,
+
+    /// Domain name of keyserver, or name of a keyserver list from the config, used for hkp lookup. Repeat or separate with commas to query several keyservers in order. if not provided will default to keys.openpgp.org
+    #[arg(
+        short,
+        long,
+        required(false),
+        value_delimiter = ',',
+        env = "KEYOXIDE_KEYSERVER_DOMAIN"
+    )]
+    pub keyserver_domain: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct ClaimArgs {
+    /// Uri of the claim, e.g. https://fosstodon.org/@alice
+    pub service_uri: String,
+
+    /// Uri of the identity the proof must reference, e.g. openpgp4fpr:<key_fingerprint> or aspe:<domain>:<fingerprint>
+    pub identity_uri: String,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct AspeArgs {
+    /// Uri of the profile (aspe:<domain>:<fingerprint>)
+    pub aspe_uri: String,
+}
+
+impl AspeArgs {
+    pub fn aspe_uri(&self) -> Result<ProfileUri, AppError> {
+        match self.aspe_uri.parse::<ProfileUri>()? {
+            aspe_uri @ ProfileUri::Aspe { .. } => Ok(aspe_uri),
+            _ => Err(AppError::InvalidAspeUri {
+                uri: self.aspe_uri.to_string(),
+                span: (0, self.aspe_uri.len()).into(),
+            }),
+        }
+    }
+}
+
+/// Flat invocation kept for backwards compatibility, prefer `keyoxide verify`
+#[derive(clap::Args, Debug)]
+pub struct LegacyArgs {
+    /// [DEPRECATED: use `keyoxide verify <URI>`] Uri for looking up a profile
+    #[arg(short, long)]
     pub doip_profile_uri: Option<String>,
 
-    /// Domain name of keyserver used for hkp lookup. if not provided will default to keys.openpgp.org
+    /// [DEPRECATED: use `keyoxide verify -k`] Domain name of keyserver used for hkp lookup
     #[arg(short, long, required(false))]
     pub keyserver_domain: Option<String>,
 
-    /// Path to file containing ASCII-Armored Public Key
+    /// [DEPRECATED: use `keyoxide verify -i`] Path to file containing an ASCII-Armored or binary Public Key
     #[arg(short, long, required(false))]
     pub input_key_file: Option<String>,
+}
+
+impl LegacyArgs {
+    pub fn is_used(&self) -> bool {
+        self.doip_profile_uri.is_some()
+            || self.keyserver_domain.is_some()
+            || self.input_key_file.is_some()
+    }
 
+    pub fn into_verify_args(self) -> VerifyArgs {
+        VerifyArgs {
+            doip_profile_uris: self.doip_profile_uri.into_iter().collect(),
+            keyserver_domain: self.keyserver_domain.into_iter().collect(),
+            input_key_file: self.input_key_file,
+            batch: None,
+            jobs: 1,
+        }
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct GlobalArgs {
     ///Print Format
     #[clap(value_enum, default_value_t)]
-    #[arg(short, long)]
+    #[arg(short, long, global = true, env = "KEYOXIDE_PRINT_FORMAT")]
     pub print_format: PrintFormat,
 
     /// Skip SSL Verification for Aspe Profile Fetch
-    #[arg(short, long)]
+    #[arg(short, long, global = true, env = "KEYOXIDE_SKIP_VERIFY_SSL")]
     pub skip_verify_ssl: bool,
 
     /// Set Logging to Quiet
-    #[arg(short, long)]
+    #[arg(short, long, global = true, env = "KEYOXIDE_QUIET")]
     pub quiet: bool,
+
+    /// Number of proofs that must be verified for the process to exit 0 (<N> || all)
+    #[arg(short, long, global = true, default_value_t, env = "KEYOXIDE_REQUIRE")]
+    pub require: RequirePolicy,
+
+    /// Maximum number of proofs verified at the same time, across every profile
+    #[arg(long, global = true, default_value_t = DEFAULT_MAX_CONCURRENT_PROOFS, env = "KEYOXIDE_MAX_CONCURRENT_PROOFS")]
+    pub max_concurrent_proofs: usize,
+
+    /// Maximum number of proofs fetched per second from the same host, 0 disables the limit
+    #[arg(long, global = true, default_value_t = DEFAULT_PER_HOST_RATE, env = "KEYOXIDE_PER_HOST_RATE")]
+    pub per_host_rate: u32,
+
+    /// Seconds after which a single proof is reported as timed out, 0 disables the timeout
+    #[arg(long, global = true, default_value_t = DEFAULT_PROOF_TIMEOUT_SECS, env = "KEYOXIDE_PROOF_TIMEOUT")]
+    pub proof_timeout: u64,
+
+    /// Seconds after which every outstanding proof is reported as timed out
+    #[arg(long, global = true, env = "KEYOXIDE_TOTAL_TIMEOUT")]
+    pub total_timeout: Option<u64>,
+
+    /// Number of times a proof or key fetch is retried after a timeout, 429 or 5xx, 0 disables retries
+    #[arg(long, global = true, default_value_t = DEFAULT_RETRIES, env = "KEYOXIDE_RETRIES")]
+    pub retries: u32,
+
+    /// Whether several keyservers are queried in order until one returns the key, or all at once
+    #[clap(value_enum, default_value_t)]
+    #[arg(long, global = true, env = "KEYOXIDE_KEYSERVER_STRATEGY")]
+    pub keyserver_strategy: KeyserverStrategy,
+
+    /// Neither read nor write the cache
+    #[arg(
+        long,
+        global = true,
+        conflicts_with("refresh"),
+        env = "KEYOXIDE_NO_CACHE"
+    )]
+    pub no_cache: bool,
+
+    /// Fetch and verify everything again, ignoring cached entries, then update the cache
+    #[arg(long, global = true)]
+    pub refresh: bool,
+
+    /// Never use the network, keys, ASP, proofs and claims only come from the cache
+    #[arg(long, global = true, conflicts_with_all(["no_cache", "refresh"]))]
+    pub offline: bool,
+
+    /// Directory holding the cache instead of $XDG_CACHE_HOME/keyoxide, e.g. a copy for offline use
+    #[arg(long, global = true, env = "KEYOXIDE_CACHE_DIR")]
+    pub cache_dir: Option<String>,
+
+    /// Don't verify the proofs of revoked, expired or invalid keys and UserIDs
+    #[arg(long, global = true, env = "KEYOXIDE_STRICT")]
+    pub strict: bool,
+
+    /// Evaluate keys, UserIDs and the proofs they hold as of this RFC 3339 time instead of now,
+    /// e.g. 2024-01-31T12:00:00Z
+    #[arg(long, global = true, env = "KEYOXIDE_AT")]
+    pub at: Option<DateTime<Utc>>,
+
+    /// HTTP(S) proxy every request goes through
+    #[arg(long, global = true, env = "KEYOXIDE_PROXY")]
+    pub proxy: Option<String>,
+
+    /// Path to the config file, defaults to $XDG_CONFIG_HOME/keyoxide/config.toml
+    #[arg(long, global = true, env = "KEYOXIDE_CONFIG")]
+    pub config: Option<String>,
+}
+
+impl GlobalArgs {
+    pub fn context(&self) -> Arc<VerifyContext> {
+        let proof_timeout = match self.proof_timeout {
+            0 => None,
+            proof_timeout => Some(Duration::from_secs(proof_timeout)),
+        };
+        Arc::new(VerifyContext {
+            limiter: ProofLimiter::new(self.max_concurrent_proofs, self.per_host_rate)
+                .with_timeouts(proof_timeout, self.total_timeout.map(Duration::from_secs)),
+            retry_policy: self.retry_policy(),
+            cache: self.cache(),
+            strict: self.strict,
+            reference_time: self.reference_time(),
+        })
+    }
+
+    pub fn cache(&self) -> Cache {
+        let cache_mode = match (self.offline, self.no_cache, self.refresh) {
+            (true, _, _) => CacheMode::Offline,
+            (false, true, _) => CacheMode::Disabled,
+            (false, false, true) => CacheMode::Refresh,
+            (false, false, false) => CacheMode::Use,
+        };
+        match &self.cache_dir {
+            Some(cache_dir) => Cache::in_dir(cache_dir, cache_mode),
+            None => Cache::new(cache_mode),
+        }
+    }
+
+    /// Keyservers behind `keyserver_domains`, with keyserver lists from `config` expanded
+    pub fn keyservers(&self, keyserver_domains: &[String], config: &Config) -> Keyservers {
+        Keyservers::new(
+            config.resolve_keyservers(keyserver_domains),
+            self.keyserver_strategy,
+        )
+    }
+
+    /// Time given with `--at`, `None` is now
+    pub fn reference_time(&self) -> Option<SystemTime> {
+        self.at.map(SystemTime::from)
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::new(self.retries)
+    }
 }
@@ -0,0 +1,173 @@
+use std::{collections::BTreeMap, env, fs, io, path::PathBuf};
+
+use super::error::AppError;
+use serde::Deserialize;
+
+/// Defaults read from `config.toml`, every key is the long name of the flag it sets.
+/// Precedence is command line flags, then `KEYOXIDE_*` env vars, then this file, then the
+/// built-in defaults
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Keyserver domains or names of `[keyservers]` lists, separated by commas
+    pub keyserver_domain: Option<String>,
+    /// Named keyserver lists, usable wherever a keyserver domain is
+    pub keyservers: BTreeMap<String, Vec<String>>,
+    /// `fallback` or `parallel`
+    pub keyserver_strategy: Option<String>,
+    /// HTTP(S) proxy every request goes through
+    pub proxy: Option<String>,
+    pub skip_verify_ssl: Option<bool>,
+    pub print_format: Option<String>,
+    pub quiet: Option<bool>,
+    pub require: Option<String>,
+    pub jobs: Option<usize>,
+    pub max_concurrent_proofs: Option<usize>,
+    pub per_host_rate: Option<u32>,
+    pub proof_timeout: Option<u64>,
+    pub total_timeout: Option<u64>,
+    pub retries: Option<u32>,
+    pub no_cache: Option<bool>,
+    pub cache_dir: Option<String>,
+    pub strict: Option<bool>,
+}
+
+impl Config {
+    /// Reads the config at `path`, or at the default location when there is no `path`. Only
+    /// an explicit `path` has to exist
+    pub fn load(path: Option<&str>) -> Result<Self, AppError> {
+        let (path, required) = match path {
+            Some(path) => (PathBuf::from(path), true),
+            None => match default_config_path() {
+                Some(path) => (path, false),
+                None => return Ok(Config::default()),
+            },
+        };
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => Config::parse(&contents),
+            Err(error) if error.kind() == io::ErrorKind::NotFound && !required => {
+                Ok(Config::default())
+            }
+            Err(error) => Err(AppError::FailedToReadConfig(error)),
+        }
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, AppError> {
+        toml::from_str(contents).map_err(|error| AppError::InvalidConfig {
+            message: error.to_string(),
+        })
+    }
+
+    /// Flag ids and values this config sets a default for
+    pub fn defaults(&self) -> Vec<(&'static str, String)> {
+        let defaults = [
+            ("keyserver_domain", self.keyserver_domain.clone()),
+            ("keyserver_strategy", self.keyserver_strategy.clone()),
+            ("proxy", self.proxy.clone()),
+            (
+                "skip_verify_ssl",
+                self.skip_verify_ssl.map(|v| v.to_string()),
+            ),
+            ("print_format", self.print_format.clone()),
+            ("quiet", self.quiet.map(|v| v.to_string())),
+            ("require", self.require.clone()),
+            ("jobs", self.jobs.map(|v| v.to_string())),
+            (
+                "max_concurrent_proofs",
+                self.max_concurrent_proofs.map(|v| v.to_string()),
+            ),
+            ("per_host_rate", self.per_host_rate.map(|v| v.to_string())),
+            ("proof_timeout", self.proof_timeout.map(|v| v.to_string())),
+            ("total_timeout", self.total_timeout.map(|v| v.to_string())),
+            ("retries", self.retries.map(|v| v.to_string())),
+            ("no_cache", self.no_cache.map(|v| v.to_string())),
+            ("cache_dir", self.cache_dir.clone()),
+            ("strict", self.strict.map(|v| v.to_string())),
+        ];
+        defaults
+            .into_iter()
+            .filter_map(|(id, value)| Some((id, value?)))
+            .collect()
+    }
+
+    /// Keyservers behind every entry of `keyservers` in order, skipping duplicates
+    pub fn resolve_keyservers(&self, keyservers: &[String]) -> Vec<String> {
+        let mut resolved_keyservers: Vec<String> = Vec::new();
+        for keyserver in keyservers.iter().flat_map(|k| self.resolve_keyserver(k)) {
+            if !resolved_keyservers.contains(&keyserver) {
+                resolved_keyservers.push(keyserver);
+            }
+        }
+        resolved_keyservers
+    }
+
+    /// Keyservers behind `keyserver`, the list of that name or the domain itself
+    pub fn resolve_keyserver(&self, keyserver: &str) -> Vec<String> {
+        match self.keyservers.get(keyserver) {
+            Some(keyservers) => keyservers.clone(),
+            None => vec![keyserver.to_string()],
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/keyoxide/config.toml`, falling back to `~/.config/keyoxide/config.toml`
+pub fn default_config_path() -> Option<PathBuf> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME") {
+        Some(config_home) if !config_home.is_empty() => PathBuf::from(config_home),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("keyoxide").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        let config = Config::parse(
+            r#"
+            keyserver-domain = "work"
+            print-format = "json"
+            retries = 5
+
+            [keyservers]
+            work = ["keys.example.org", "keys.openpgp.org"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.defaults(),
+            [
+                ("keyserver_domain", "work".to_string()),
+                ("print_format", "json".to_string()),
+                ("retries", "5".to_string())
+            ]
+        );
+        assert_eq!(
+            config.resolve_keyserver("work"),
+            ["keys.example.org", "keys.openpgp.org"]
+        );
+        assert_eq!(
+            config.resolve_keyserver("keyserver.ubuntu.com"),
+            ["keyserver.ubuntu.com"]
+        );
+        assert_eq!(
+            config.resolve_keyservers(&["keyserver.ubuntu.com".to_string(), "work".to_string()]),
+            [
+                "keyserver.ubuntu.com",
+                "keys.example.org",
+                "keys.openpgp.org"
+            ]
+        );
+    }
+
+    #[test]
+    fn reject_unknown_keys() {
+        assert!(matches!(
+            Config::parse("keyserver = \"keys.openpgp.org\""),
+            Err(AppError::InvalidConfig { .. })
+        ));
+    }
+}
@@ -0,0 +1,28 @@
+use std::time::SystemTime;
+
+use super::{cache::Cache, limit::ProofLimiter, retry::RetryPolicy};
+
+/// What every fetch and proof made while verifying profiles shares: the limits on requests,
+/// how failed requests are retried, the cache and how keys are evaluated
+#[derive(Debug, Default)]
+pub struct VerifyContext {
+    /// Bounds the proofs verified at once, per host and in time
+    pub limiter: ProofLimiter,
+    /// Retry policy for every proof and fetch made while verifying a profile
+    pub retry_policy: RetryPolicy,
+    /// Cache of certs, JWS, proof and claim results, disabled by default
+    pub cache: Cache,
+    /// Whether proofs of revoked, expired or invalid keys and UserIDs are left unverified
+    pub strict: bool,
+    /// Time keys, UserIDs and the proofs they hold are evaluated at, `None` is now
+    pub reference_time: Option<SystemTime>,
+}
+
+/// Context recording and replaying `__tests__/cassettes`, see [`Cache::cassette`]
+#[cfg(test)]
+pub(crate) fn cassette_context() -> VerifyContext {
+    VerifyContext {
+        cache: Cache::cassette("__tests__/cassettes"),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,277 @@
+use super::{
+    context::VerifyContext,
+    doip::{PrintFormat, Printable, VerifiedProof},
+    error::AppError,
+    keyserver::{FetchedCert, Keyservers, merge_fetched_cert},
+    limit::run_until,
+    openpgp::{KeyProfile, fetch_certs},
+    retry::RetryPolicy,
+    uri::ProfileUri,
+    wkd::WkdLookup,
+};
+use display_json::{DebugAsJsonPretty, DisplayAsJson};
+use miette::Result;
+use serde::Serialize;
+
+/// Ways profiles are looked up from an email address, in the order they are tried
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscoveryMethod {
+    Wkd,
+    Keyserver,
+}
+
+impl DiscoveryMethod {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DiscoveryMethod::Wkd => "WKD",
+            DiscoveryMethod::Keyserver => "Keyservers",
+        }
+    }
+}
+
+/// What a single discovery method found
+#[derive(Serialize)]
+pub struct DiscoveryAttempt {
+    pub method: DiscoveryMethod,
+    /// Fingerprints of the keys found
+    pub found: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Method, requests and policy of the WKD lookup, for WKD attempts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wkd: Option<WkdLookup>,
+}
+
+impl DiscoveryAttempt {
+    /// What each method found, read from where the discovered certs came from so cached certs
+    /// report them too. Keyservers that failed are reported as having found nothing
+    fn from_certs(certs: &[FetchedCert]) -> Vec<Self> {
+        let wkd_certs: Vec<&FetchedCert> = certs
+            .iter()
+            .filter(|fetched_cert| {
+                fetched_cert
+                    .wkd
+                    .as_ref()
+                    .is_some_and(|wkd_lookup| wkd_lookup.method.is_some())
+            })
+            .collect();
+        let wkd_lookup = wkd_certs
+            .first()
+            .copied()
+            .or(certs.first())
+            .and_then(|fetched_cert| fetched_cert.wkd.clone())
+            .map(|wkd_lookup| WkdLookup {
+                warnings: Vec::new(),
+                ..wkd_lookup
+            });
+        let wkd_error = match (wkd_certs.is_empty(), &wkd_lookup) {
+            (true, Some(wkd_lookup)) => Some(
+                AppError::WkdKeyNotFound {
+                    email_address: wkd_lookup.email_address.clone(),
+                    requests: wkd_lookup.requests_summary(),
+                }
+                .to_string(),
+            ),
+            _ => None,
+        };
+
+        vec![
+            DiscoveryAttempt {
+                method: DiscoveryMethod::Wkd,
+                found: wkd_certs.into_iter().map(fingerprint).collect(),
+                error: wkd_error,
+                wkd: wkd_lookup,
+            },
+            DiscoveryAttempt {
+                method: DiscoveryMethod::Keyserver,
+                found: certs
+                    .iter()
+                    .filter(|fetched_cert| !fetched_cert.keyservers.is_empty())
+                    .map(fingerprint)
+                    .collect(),
+                error: None,
+                wkd: None,
+            },
+        ]
+    }
+}
+
+/// Every distinct key found for an email address, along with how each was found
+#[derive(Serialize, DisplayAsJson, DebugAsJsonPretty)]
+pub struct EmailProfile {
+    pub email_address: String,
+    pub attempts: Vec<DiscoveryAttempt>,
+    pub key_profiles: Vec<KeyProfile>,
+}
+
+impl Printable for EmailProfile {
+    fn print(&self, print_format: &PrintFormat) {
+        match print_format {
+            PrintFormat::Json | PrintFormat::Ndjson => println!("{self}"),
+            PrintFormat::JsonPretty => println!("{self:?}"),
+            PrintFormat::Text => {
+                let mut print = String::new();
+                print.push_str(format!("Email: {}\n", self.email_address).as_str());
+
+                for attempt in &self.attempts {
+                    let found = match (&attempt.error, attempt.found.is_empty()) {
+                        (Some(error), _) => format!("failed, {error}"),
+                        (None, true) => "nothing found".to_string(),
+                        (None, false) => attempt.found.join(", "),
+                    };
+                    print.push_str(format!("  {}: {found}\n", attempt.method.name()).as_str());
+                    if let Some(wkd_lookup) = &attempt.wkd {
+                        if let Some(method) = wkd_lookup.method {
+                            print.push_str(format!("    Method: {}\n", method.name()).as_str());
+                        }
+                        for request in &wkd_lookup.requests {
+                            print.push_str(format!("    {request}\n").as_str());
+                        }
+                    }
+                }
+
+                print!("{}", print);
+                for key_profile in &self.key_profiles {
+                    key_profile.print(print_format);
+                }
+            }
+        }
+    }
+}
+
+impl EmailProfile {
+    /// Looks up every key of `email_address` over WKD, advanced then direct, and the
+    /// keyservers in that order, then verifies each distinct one found. The keys found are
+    /// cached like those of any other profile uri
+    pub async fn new(
+        email_address: &str,
+        keyservers: &Keyservers,
+        context: &VerifyContext,
+    ) -> Result<Self> {
+        let profile_uri = ProfileUri::Email(email_address.to_string());
+        let certs = run_until(
+            context.limiter.deadline(),
+            fetch_certs(
+                &profile_uri,
+                keyservers,
+                &context.retry_policy,
+                &context.cache,
+            ),
+        )
+        .await
+        .ok_or(AppError::TotalTimeoutElapsed)??;
+
+        Ok(EmailProfile {
+            email_address: email_address.to_string(),
+            attempts: DiscoveryAttempt::from_certs(&certs),
+            key_profiles: KeyProfile::new_from_certs(certs, context).await?,
+        })
+    }
+
+    /// Every proof of every key found
+    pub fn proofs(&self) -> impl Iterator<Item = &VerifiedProof> {
+        self.key_profiles.iter().flat_map(KeyProfile::proofs)
+    }
+
+    /// Number of keys found
+    pub fn profile_count(&self) -> usize {
+        self.key_profiles.len()
+    }
+}
+
+/// Looks up the keys of `email_address` over WKD and then the keyservers, merging keys found by
+/// both by fingerprint. Keys found over WKD carry how they were looked up, when WKD found
+/// nothing the keys found on the keyservers carry the failed lookup instead
+pub async fn discover_certs(
+    email_address: &str,
+    keyservers: &Keyservers,
+    retry_policy: &RetryPolicy,
+) -> Result<Vec<FetchedCert>> {
+    let mut fetched_certs = Vec::new();
+
+    let (certs, wkd_lookup) = WkdLookup::new(email_address, retry_policy).await;
+    for cert in certs {
+        merge_fetched_cert(
+            &mut fetched_certs,
+            FetchedCert {
+                wkd: Some(wkd_lookup.for_cert(&cert)),
+                cert,
+                keyservers: Vec::new(),
+            },
+        );
+    }
+    let wkd_missed = fetched_certs.is_empty();
+
+    for mut fetched_cert in keyservers
+        .fetch(email_address, retry_policy)
+        .await
+        .unwrap_or_default()
+    {
+        if wkd_missed {
+            fetched_cert.wkd = Some(wkd_lookup.clone());
+        }
+        merge_fetched_cert(&mut fetched_certs, fetched_cert);
+    }
+
+    match fetched_certs.is_empty() {
+        true => Err(AppError::NothingDiscovered {
+            email_address: email_address.to_string(),
+        }
+        .into()),
+        false => Ok(fetched_certs),
+    }
+}
+
+fn fingerprint(fetched_cert: &FetchedCert) -> String {
+    fetched_cert.cert.fingerprint().to_hex()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::cache::{Cache, CacheMode};
+    use sequoia_openpgp::{Cert, parse::Parse};
+
+    #[test]
+    fn attempts_are_read_from_the_discovered_certs() {
+        let wkd_lookup = WkdLookup {
+            email_address: "test@doip.rocks".to_string(),
+            method: None,
+            requests: Vec::new(),
+            policy: None,
+            warnings: Vec::new(),
+        };
+        let attempts = DiscoveryAttempt::from_certs(&[FetchedCert {
+            cert: Cert::from_file("__tests__/data/TEST_DOIP_PUBLIC_KEY.asc").unwrap(),
+            keyservers: vec!["keys.openpgp.org".to_string()],
+            wkd: Some(wkd_lookup),
+        }]);
+
+        assert_eq!(attempts[0].method, DiscoveryMethod::Wkd);
+        assert!(attempts[0].found.is_empty());
+        assert!(attempts[0].error.is_some());
+        assert_eq!(attempts[1].method, DiscoveryMethod::Keyserver);
+        assert_eq!(
+            attempts[1].found,
+            ["3637202523E7C1309AB79E99EF2DC5827B445F4B"]
+        );
+    }
+
+    #[tokio::test]
+    async fn offline_discovery_only_uses_the_cache() {
+        let context = VerifyContext {
+            cache: Cache::in_dir("__tests__/cassettes", CacheMode::Offline),
+            ..Default::default()
+        };
+        let error = EmailProfile::new("nobody@doip.rocks", &Keyservers::default(), &context)
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(
+            error.downcast_ref::<AppError>(),
+            Some(AppError::NotAvailableOffline { .. })
+        ));
+    }
+}
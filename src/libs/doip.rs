@@ -1,81 +1,586 @@
 use display_json::{DebugAsJsonPretty, DisplayAsJson};
 use doip::{
     claim::{Claim, VerificationResult},
-    service_provider::SPAbout,
+    service_provider::ServiceProvider,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-use super::error::ProofError;
+use super::{
+    cache::CacheKind, context::VerifyContext, error::ProofError, limit::run_until,
+    openpgp::ProofSignature,
+};
 
 #[derive(clap::ValueEnum, Clone, Debug, Default)]
 pub enum PrintFormat {
     Json,
     JsonPretty,
+    /// One JSON document per line, batches are streamed entry by entry
+    Ndjson,
     #[default]
     Text,
 }
 
-pub trait Profile {
+pub trait Printable {
     fn print(&self, print_format: &PrintFormat);
 }
 
-#[derive(Serialize, DisplayAsJson, DebugAsJsonPretty)]
+/// Outcome of a proof, shared by every printer and the JSON output
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProofStatus {
+    /// The proof was fetched and references the profile
+    Verified,
+    /// The proof was fetched but does not reference the profile
+    NotVerified,
+    /// A service provider matched but fetching or checking the proof failed
+    Error,
+    /// No service provider matched the claim
+    NoMatchingProvider,
+    /// The proof was still being verified when `--proof-timeout` or `--total-timeout` elapsed
+    TimedOut,
+    /// The proof isn't cached and `--offline` forbids fetching it
+    UnverifiableOffline,
+    /// The key or UserID holding the proof is revoked, expired or invalid and `--strict` is set,
+    /// or the proof was read from a self-signature that isn't the current binding signature
+    InvalidKey,
+}
+
+impl ProofStatus {
+    pub fn new(
+        verification_result: Option<&AppVerificationResult>,
+        error: Option<&ProofFailure>,
+    ) -> Self {
+        match (verification_result, error) {
+            (Some(verification_result), _) if verification_result.result => ProofStatus::Verified,
+            (Some(_), _) => ProofStatus::NotVerified,
+            (None, Some(error)) if error.kind == FailureKind::TimedOut => ProofStatus::TimedOut,
+            (None, Some(error)) if error.kind == FailureKind::NoMatchingProvider => {
+                ProofStatus::NoMatchingProvider
+            }
+            (None, _) => ProofStatus::Error,
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            ProofStatus::Verified => "✅",
+            ProofStatus::NotVerified => "❌",
+            ProofStatus::Error => "⚠️",
+            ProofStatus::NoMatchingProvider => "❔",
+            ProofStatus::TimedOut => "⏱️",
+            ProofStatus::UnverifiableOffline => "📴",
+            ProofStatus::InvalidKey => "🚫",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, DisplayAsJson, DebugAsJsonPretty)]
 pub struct VerifiedProof {
     pub uri: String,
+    pub status: ProofStatus,
     pub verification_result: Option<AppVerificationResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ProofFailure>,
+    /// Number of times the proof was fetched, more than 1 when transient failures were retried
+    pub attempts: u32,
+    /// Self-signature the proof was read from, for proofs of OpenPGP keys
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub signature: Option<ProofSignature>,
 }
 
 impl VerifiedProof {
-    pub fn new(proof: String, verification_result: Option<VerificationResult>) -> VerifiedProof {
+    pub fn new(proof: String, proof_verification: ProofVerification) -> VerifiedProof {
+        let attempts = proof_verification.attempts;
+        let (status, verification_result, error) = proof_verification.outcome();
+        VerifiedProof {
+            uri: proof,
+            status,
+            verification_result,
+            error,
+            attempts,
+            signature: None,
+        }
+    }
+
+    /// A proof that could not be checked because it isn't cached and nothing may be fetched
+    pub fn unverifiable_offline(proof: String) -> VerifiedProof {
+        VerifiedProof {
+            uri: proof,
+            status: ProofStatus::UnverifiableOffline,
+            verification_result: None,
+            error: None,
+            attempts: 0,
+            signature: None,
+        }
+    }
+
+    /// A proof left unverified because the key or UserID holding it isn't valid, or the
+    /// signature it was read from isn't the binding signature
+    pub fn invalid_key(proof: String) -> VerifiedProof {
         VerifiedProof {
             uri: proof,
-            verification_result: verification_result.map(AppVerificationResult::from),
+            status: ProofStatus::InvalidKey,
+            verification_result: None,
+            error: None,
+            attempts: 0,
+            signature: None,
+        }
+    }
+
+    pub fn with_signature(mut self, signature: ProofSignature) -> Self {
+        self.signature = Some(signature);
+        self
+    }
+
+    pub fn is_verified(&self) -> bool {
+        self.status == ProofStatus::Verified
+    }
+
+    /// Status symbol for text output, with the number of attempts when the proof was retried
+    pub fn status_text(&self) -> String {
+        match self.attempts {
+            0 | 1 => self.status.symbol().to_string(),
+            attempts => format!("{} ({attempts} attempts)", self.status.symbol()),
         }
     }
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct AppVerificationResult {
-    result: bool,
-    service_provider_info: Option<SPAbout>,
-    proxy_used: Option<String>,
+    pub result: bool,
+    /// `about` of the service provider that verified the proof
+    pub service_provider_info: Option<Value>,
+    pub proxy_used: Option<String>,
 }
 
 impl From<VerificationResult> for AppVerificationResult {
     fn from(verification_result: VerificationResult) -> Self {
         AppVerificationResult {
             result: verification_result.result,
-            service_provider_info: match verification_result.service_provider {
-                Some(service_provier) => Some(service_provier.about),
-                None => None,
-            },
+            service_provider_info: verification_result
+                .service_provider
+                .map(|service_provider| {
+                    serde_json::to_value(service_provider.about).unwrap_or(Value::Null)
+                }),
             proxy_used: verification_result.proxy_used,
         }
     }
 }
 
+/// What went wrong with a proof, read from the typed error chain of the failure
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    /// The claim matched no service provider
+    NoMatchingProvider,
+    /// The proof was fetched but does not reference the identity
+    NotVerified,
+    /// `--proof-timeout` or `--total-timeout` elapsed before the proof was verified
+    TimedOut,
+    /// A request made for the proof timed out
+    RequestTimeout,
+    /// The host of the proof or proxy could not be reached
+    Connect,
+    /// The host of the proof or proxy answered with an error status
+    HttpStatus,
+    /// The response could not be read
+    Decode,
+    /// A request made for the proof failed otherwise
+    Request,
+    /// doip rejected the claim or the proof without making a failing request
+    Doip,
+}
+
+impl FailureKind {
+    /// Classifies `error` by the request that caused it, if any
+    pub fn new(error: &(dyn std::error::Error + 'static)) -> Self {
+        match request_error(error) {
+            Some(error) if error.is_timeout() => FailureKind::RequestTimeout,
+            Some(error) if error.is_connect() => FailureKind::Connect,
+            Some(error) if error.is_status() => FailureKind::HttpStatus,
+            Some(error) if error.is_decode() || error.is_body() => FailureKind::Decode,
+            Some(_) => FailureKind::Request,
+            None => FailureKind::Doip,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FailureKind::NoMatchingProvider => "no matching service provider",
+            FailureKind::NotVerified => "not verified",
+            FailureKind::TimedOut => "timed out",
+            FailureKind::RequestTimeout => "request timed out",
+            FailureKind::Connect => "connection failed",
+            FailureKind::HttpStatus => "http error",
+            FailureKind::Decode => "unreadable response",
+            FailureKind::Request => "request failed",
+            FailureKind::Doip => "doip error",
+        }
+    }
+}
+
+/// The request failure somewhere in the source chain of `error`
+pub(crate) fn request_error<'a>(
+    error: &'a (dyn std::error::Error + 'static),
+) -> Option<&'a reqwest::Error> {
+    let mut source = Some(error);
+    while let Some(error) = source {
+        if let Some(request_error) = error.downcast_ref::<reqwest::Error>() {
+            return Some(request_error);
+        }
+        source = error.source();
+    }
+    None
+}
+
+/// Why a proof could not be verified
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ProofFailure {
+    pub kind: FailureKind,
+    pub message: String,
+    /// Status the proof or proxy answered with, for `http_status` failures
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub http_status: Option<u16>,
+    pub matched_service_providers: Vec<MatchedServiceProvider>,
+    /// Whether the failing request went to a proxy rather than the host of the proof
+    pub proxy_attempted: bool,
+}
+
+impl ProofFailure {
+    pub fn new(
+        error: &(dyn std::error::Error + 'static),
+        matched_service_providers: Vec<MatchedServiceProvider>,
+    ) -> Self {
+        let request_error = request_error(error);
+        let proxy_attempted = request_error
+            .and_then(reqwest::Error::url)
+            .is_some_and(|url| {
+                !matched_service_providers.is_empty()
+                    && !matched_service_providers.iter().any(|service_provider| {
+                        reqwest::Url::parse(&service_provider.proof_location)
+                            .is_ok_and(|proof_url| proof_url.host_str() == url.host_str())
+                    })
+            });
+
+        ProofFailure {
+            kind: FailureKind::new(error),
+            message: error.to_string(),
+            http_status: request_error
+                .and_then(reqwest::Error::status)
+                .map(|status| status.as_u16()),
+            matched_service_providers,
+            proxy_attempted,
+        }
+    }
+
+    /// The claim uri matched none of the service providers known to doip
+    pub fn no_matching_provider() -> Self {
+        ProofFailure {
+            kind: FailureKind::NoMatchingProvider,
+            message: "No service provider matched the claim".to_string(),
+            http_status: None,
+            matched_service_providers: Vec::new(),
+            proxy_attempted: false,
+        }
+    }
+
+    /// The proof was fetched but the identity was not found in it
+    pub fn not_verified(matched_service_providers: Vec<MatchedServiceProvider>) -> Self {
+        ProofFailure {
+            kind: FailureKind::NotVerified,
+            message: "The proof was fetched but does not reference the identity".to_string(),
+            http_status: None,
+            matched_service_providers,
+            proxy_attempted: false,
+        }
+    }
+
+    /// The proof was cancelled because a timeout elapsed before it was verified
+    pub fn timed_out(matched_service_providers: Vec<MatchedServiceProvider>) -> Self {
+        ProofFailure {
+            kind: FailureKind::TimedOut,
+            message: "Timed out before the proof was verified".to_string(),
+            http_status: None,
+            matched_service_providers,
+            proxy_attempted: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MatchedServiceProvider {
+    pub id: String,
+    pub name: String,
+    pub homepage: String,
+    /// Where the proof is fetched from for this service provider
+    pub proof_location: String,
+}
+
+impl MatchedServiceProvider {
+    pub fn new(service_provider: &ServiceProvider) -> Self {
+        MatchedServiceProvider {
+            id: service_provider.about.id.clone(),
+            name: service_provider.about.name.clone(),
+            homepage: service_provider.about.homepage.clone(),
+            proof_location: service_provider.proof.request.uri.clone(),
+        }
+    }
+}
+
+/// What fetching and verifying a proof found out, shared by proofs and `keyoxide claim`
+pub struct ProofVerification {
+    /// Number of times the proof was fetched, 0 when it wasn't
+    pub attempts: u32,
+    /// Service providers the claim matched, empty when it matched none
+    pub matched_service_providers: Vec<MatchedServiceProvider>,
+    pub result: Result<VerificationResult, ProofFailure>,
+}
+
+impl ProofVerification {
+    /// Status, result and failure to report, a proof that was fetched but doesn't reference
+    /// the identity fails with the service providers it was fetched from
+    pub fn outcome(
+        self,
+    ) -> (
+        ProofStatus,
+        Option<AppVerificationResult>,
+        Option<ProofFailure>,
+    ) {
+        let (verification_result, error) = match self.result {
+            Ok(verification_result) if !verification_result.result => (
+                Some(AppVerificationResult::from(verification_result)),
+                Some(ProofFailure::not_verified(self.matched_service_providers)),
+            ),
+            Ok(verification_result) => {
+                (Some(AppVerificationResult::from(verification_result)), None)
+            }
+            Err(error) => (None, Some(error)),
+        };
+        (
+            ProofStatus::new(verification_result.as_ref(), error.as_ref()),
+            verification_result,
+            error,
+        )
+    }
+}
+
+/// Verifies a single proof, reusing a fresh cached result when there is one. Only verified and
+/// not verified results are cached as errors and timeouts may not happen again. Offline, proofs
+/// that aren't cached are reported as unverifiable. doip fetches the proof document itself, so
+/// the result is cached in place of the document
 pub async fn verify_proof(
     service_uri: String,
     proof_uri: String,
-) -> (String, Option<VerificationResult>) {
+    context: &VerifyContext,
+) -> VerifiedProof {
+    let cache_key = format!("{service_uri} {proof_uri}");
+    if let Some(mut verified_proof) = context
+        .cache
+        .get::<VerifiedProof>(CacheKind::Proof, &cache_key)
+    {
+        // Nothing was fetched this time
+        verified_proof.attempts = 0;
+        return verified_proof;
+    }
+    if context.cache.is_offline() {
+        return VerifiedProof::unverifiable_offline(service_uri);
+    }
+
+    let proof_verification = fetch_and_verify_proof(&service_uri, &proof_uri, context).await;
+    let verified_proof = VerifiedProof::new(service_uri, proof_verification);
+    if matches!(
+        verified_proof.status,
+        ProofStatus::Verified | ProofStatus::NotVerified
+    ) {
+        context
+            .cache
+            .put(CacheKind::Proof, &cache_key, &verified_proof);
+    }
+    verified_proof
+}
+
+/// Verifies a single proof once the limiter of `context` allows another request to its host, retrying
+/// transient failures and giving up when its proof or total timeout elapses. doip doesn't hand
+/// back the response of a failed proof fetch, so retries back off without `Retry-After`
+pub(crate) async fn fetch_and_verify_proof(
+    service_uri: &str,
+    proof_uri: &str,
+    context: &VerifyContext,
+) -> ProofVerification {
     let claim = Claim::new(service_uri.to_string(), proof_uri.to_string());
     match claim.find_matches() {
-        Ok(matches) => (
-            service_uri.to_string(),
-            claim
-                .verify_with_matches(matches)
-                .await
-                .map_err(|error| {
+        Ok(matches) if matches.is_empty() => ProofVerification {
+            attempts: 0,
+            matched_service_providers: Vec::new(),
+            result: Err(ProofFailure::no_matching_provider()),
+        },
+        Ok(matches) => {
+            let matched_service_providers: Vec<MatchedServiceProvider> =
+                matches.iter().map(MatchedServiceProvider::new).collect();
+
+            let permit = run_until(
+                context.limiter.deadline(),
+                context.limiter.acquire(service_uri),
+            )
+            .await;
+            let Some(permit) = permit else {
+                return ProofVerification {
+                    attempts: 0,
+                    result: Err(ProofFailure::timed_out(matched_service_providers.clone())),
+                    matched_service_providers,
+                };
+            };
+
+            let claim = &claim;
+            let host_uri = service_uri;
+            let mut permit = Some(permit);
+            let mut attempts = 0;
+            let verification_result = match run_until(
+                context.limiter.proof_deadline(),
+                context.retry_policy.run_counting(&mut attempts, move || {
+                    // Retries wait for a slot and for the host again, so they count
+                    // against the concurrency and per host limits like any other request
+                    let permit = permit.take();
+                    async move {
+                        let _permit = match permit {
+                            Some(permit) => permit,
+                            None => context.limiter.acquire(host_uri).await,
+                        };
+                        claim.verify_with_matches(claim.find_matches()?).await
+                    }
+                }),
+            )
+            .await
+            {
+                Some(verification_result) => verification_result.map_err(|error| {
+                    let proof_failure =
+                        ProofFailure::new(&error, matched_service_providers.clone());
                     ProofError::from(proof_uri.to_string(), service_uri.to_string(), error)
                         .warn_proof_errors();
-                    None::<VerificationResult>
-                })
-                .ok(),
-        ),
+                    proof_failure
+                }),
+                None => Err(ProofFailure::timed_out(matched_service_providers.clone())),
+            };
+            ProofVerification {
+                attempts,
+                matched_service_providers,
+                result: verification_result,
+            }
+        }
+        // Matching is done locally, nothing was fetched
         Err(error) => {
+            let proof_failure = ProofFailure::new(&error, Vec::new());
             ProofError::from(proof_uri.to_string(), service_uri.to_string(), error)
                 .warn_proof_errors();
-            (service_uri.to_string(), None)
+            ProofVerification {
+                attempts: 0,
+                matched_service_providers: Vec::new(),
+                result: Err(proof_failure),
+            }
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::{
+        io::{self, Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    /// Answers the connections to a local port with `responses` in order, repeating the last
+    /// one, returns the url to request
+    pub(crate) fn serve(responses: Vec<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let mut responses = responses.into_iter().peekable();
+            for mut stream in listener.incoming().flatten() {
+                let response = match responses.len() {
+                    1 => responses.peek().copied(),
+                    _ => responses.next(),
+                };
+                let _ = stream.read(&mut [0; 4096]);
+                let _ = stream.write_all(response.unwrap_or_default().as_bytes());
+            }
+        });
+        url
+    }
+
+    /// Url of a local port nothing listens on
+    pub(crate) fn refused_url() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}/", listener.local_addr().unwrap())
+    }
+
+    fn verification_result(result: bool) -> AppVerificationResult {
+        AppVerificationResult {
+            result,
+            service_provider_info: None,
+            proxy_used: None,
+        }
+    }
+
+    #[test]
+    fn proof_status() {
+        assert_eq!(
+            ProofStatus::new(Some(&verification_result(true)), None),
+            ProofStatus::Verified
+        );
+        assert_eq!(
+            ProofStatus::new(
+                Some(&verification_result(false)),
+                Some(&ProofFailure::not_verified(Vec::new()))
+            ),
+            ProofStatus::NotVerified
+        );
+        assert_eq!(
+            ProofStatus::new(None, Some(&ProofFailure::no_matching_provider())),
+            ProofStatus::NoMatchingProvider
+        );
+        assert_eq!(
+            ProofStatus::new(None, Some(&ProofFailure::timed_out(Vec::new()))),
+            ProofStatus::TimedOut
+        );
+        // A failure before any provider was matched, e.g. an invalid claim, is an error and
+        // not a missing provider
+        let invalid_claim = ProofFailure::new(&io::Error::other("invalid claim"), Vec::new());
+        assert_eq!(
+            ProofStatus::new(None, Some(&invalid_claim)),
+            ProofStatus::Error
+        );
+        assert_eq!(ProofStatus::new(None, None), ProofStatus::Error);
+    }
+
+    #[derive(thiserror::Error, Debug)]
+    #[error("wrapped")]
+    struct Wrapped(#[source] reqwest::Error);
+
+    #[tokio::test]
+    async fn failures_are_classified_by_their_request_error() {
+        let url = serve(vec![
+            "HTTP/1.1 503 Service Unavailable\r\nconnection: close\r\ncontent-length: 0\r\n\r\n",
+        ]);
+        let error = reqwest::get(&url)
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap_err();
+        assert_eq!(FailureKind::new(&error), FailureKind::HttpStatus);
+        assert_eq!(
+            request_error(&Wrapped(error)).and_then(reqwest::Error::status),
+            Some(reqwest::StatusCode::SERVICE_UNAVAILABLE)
+        );
+
+        let error = reqwest::get(refused_url()).await.unwrap_err();
+        assert_eq!(FailureKind::new(&Wrapped(error)), FailureKind::Connect);
+
+        assert_eq!(
+            FailureKind::new(&io::Error::other("not a request")),
+            FailureKind::Doip
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use doip::error::DoipError;
 
-use miette::{Diagnostic, IntoDiagnostic, ReportHandler};
+use miette::{Diagnostic, IntoDiagnostic, ReportHandler, SourceSpan};
 use std::{
     env, fmt,
     io::{self, prelude::*, stderr},
@@ -12,24 +12,93 @@ pub enum AppError {
     // #[error(transparent)]
     // #[diagnostic(code(my_lib::io_error))]
     // IoError(#[from] std::io::Error),
-    #[error("DOIP_PROFILE_URI does not match 'hkp:', 'hkps:', 'wkd:' or 'aspe:' pattern")]
+    #[error(
+        "DOIP_PROFILE_URI does not match 'hkp:', 'hkps:', 'wkd:', 'email:', 'aspe:', 'openpgp4fpr:', 'file:' or 'https:' pattern"
+    )]
     #[diagnostic(
         code(E0001),
         help(
-            "Make sure `-d, --doip-profile-uri <DOIP_PROFILE_URI>` follows one of these patterns (hkp(s):<email_address> || hkp(s):<key_fingerprint> || wkd:<email_address> || aspe:<profile_uri>)"
+            "Make sure `<DOIP_PROFILE_URI>` follows one of these patterns (hkp(s):<email_address> || hkp(s):<key_fingerprint> || hkp(s):<key_id> || wkd:<email_address> || email:<email_address> || aspe:<domain>:<fingerprint> || openpgp4fpr:<key_fingerprint> || file:<path> || https://<url>)"
+        )
+    )]
+    ProfileURIMalformed {
+        #[source_code]
+        uri: String,
+        #[label("unknown or incomplete uri")]
+        span: SourceSpan,
+    },
+
+    #[error("DOIP_PROFILE_URI contains an invalid email address")]
+    #[diagnostic(
+        code(E0007),
+        help("Email addresses must look like <local_part>@<domain>, e.g. test@doip.rocks")
+    )]
+    InvalidEmailAddress {
+        #[source_code]
+        uri: String,
+        #[label("not a valid email address")]
+        span: SourceSpan,
+    },
+
+    #[error("DOIP_PROFILE_URI contains an invalid key fingerprint")]
+    #[diagnostic(
+        code(E0008),
+        help(
+            "Key fingerprints are 40 (v4) or 64 (v6) hexadecimal characters, hkp uris also take 16 character key ids, both optionally prefixed with `0x`"
+        )
+    )]
+    InvalidFingerprint {
+        #[source_code]
+        uri: String,
+        #[label("not a valid fingerprint")]
+        span: SourceSpan,
+    },
+
+    #[error("DOIP_PROFILE_URI is not a valid aspe uri")]
+    #[diagnostic(
+        code(E0023),
+        help(
+            "Aspe uris look like aspe:<domain>:<fingerprint> where the fingerprint is 26 uppercase base32 characters"
+        )
+    )]
+    InvalidAspeUri {
+        #[source_code]
+        uri: String,
+        #[label("invalid here")]
+        span: SourceSpan,
+    },
+
+    #[error("{uri} does not point at an OpenPGP key")]
+    #[diagnostic(code(E0010), help("Use `keyoxide aspe` to work with aspe uris"))]
+    ExpectedKeyUri { uri: String },
+
+    #[error("No key with fingerprint {fingerprint} was found")]
+    #[diagnostic(
+        code(E0011),
+        help(
+            "openpgp4fpr: uris are resolved on the keyserver (`-k, --keyserver-domain`) first, WKD can only be tried for the email addresses of the key found or given as `openpgp4fpr:<key_fingerprint>#a=<email_address>`"
         )
     )]
-    ProfileURIMalformed,
+    Openpgp4fprNotFound { fingerprint: String },
 
     #[error("No key was provided")]
     #[diagnostic(
         code(E0404),
         help(
-            "Neither `-d, --doip-profile-uri <DOIP_PROFILE_URI>` or `-i, --input-key-file <INPUT_KEY_FILE>` was provided"
+            "Neither `<DOIP_PROFILE_URI>` or `-i, --input-key-file <INPUT_KEY_FILE>` was provided"
         )
     )]
     ProfileNotProvided,
 
+    #[error("Deprecated top-level options can't be combined with a subcommand")]
+    #[diagnostic(
+        code(E0005),
+        help(
+            "Pass `-d`, `-k` and `-i` to the subcommand instead, e.g. `keyoxide verify <DOIP_PROFILE_URI>`"
+        )
+    )]
+    LegacyArgsWithSubcommand,
+
     #[allow(dead_code)]
     #[error("Sorry this code path is Unimplemented")]
     #[diagnostic(code(E0000))]
@@ -42,6 +111,17 @@ pub enum AppError {
     #[diagnostic(code(E0002))]
     FailedToReadKeyFile(#[from] io::Error),
 
+    #[error("Failed to Read Batch File")]
+    #[diagnostic(code(E0013))]
+    FailedToReadBatchFile(#[source] io::Error),
+
+    #[error("Failed to parse key: {message}")]
+    #[diagnostic(
+        code(E0012),
+        help("Keys must be OpenPGP Public Keys, either ASCII-Armored or binary")
+    )]
+    FailedToParseKey { message: String },
+
     #[error("Failed to parse AspeUri please check format")]
     #[diagnostic(code(E0003))]
     FailedToParseAspeUri,
@@ -53,6 +133,137 @@ pub enum AppError {
     #[error("Failed to fetch aspe JWT")]
     #[diagnostic(code(E0004))]
     FailedToFetchAspeJWT(#[from] reqwest::Error),
+
+    #[error("Failed to fetch key")]
+    #[diagnostic(code(E0009))]
+    FailedToFetchKey(#[source] reqwest::Error),
+
+    #[error("Timed out while fetching the profile")]
+    #[diagnostic(
+        code(E0014),
+        help("`--total-timeout` elapsed before the profile could be fetched, try raising it")
+    )]
+    TotalTimeoutElapsed,
+
+    #[error("Failed to access the cache")]
+    #[diagnostic(
+        code(E0015),
+        help(
+            "The cache lives in $XDG_CACHE_HOME/keyoxide or ~/.cache/keyoxide, use `--no-cache` to skip it"
+        )
+    )]
+    FailedToAccessCache(#[source] io::Error),
+
+    #[error("{uri} is not cached and can't be fetched offline")]
+    #[diagnostic(
+        code(E0016),
+        help(
+            "Run once without `--offline` to cache it, or point `--cache-dir` at a directory holding a copy of the cache"
+        )
+    )]
+    NotAvailableOffline { uri: String },
+
+    #[error("Failed to Read Config File")]
+    #[diagnostic(code(E0017))]
+    FailedToReadConfig(#[source] io::Error),
+
+    #[error("Invalid config file: {message}")]
+    #[diagnostic(
+        code(E0018),
+        help(
+            "Config keys are the long names of flags, e.g. `keyserver-domain = \"keys.openpgp.org\"`"
+        )
+    )]
+    InvalidConfig { message: String },
+
+    #[error("No key was found for {email_address}")]
+    #[diagnostic(
+        code(E0019),
+        help(
+            "Tried WKD advanced, WKD direct and the keyservers given with `-k, --keyserver-domain`"
+        )
+    )]
+    NothingDiscovered { email_address: String },
+
+    #[error("No key was found over WKD for {email_address}")]
+    #[diagnostic(
+        code(E0020),
+        help(
+            "Tried the advanced method, then the direct method:\n{requests}\nCheck the directory serves the key under these urls"
+        )
+    )]
+    WkdKeyNotFound {
+        email_address: String,
+        requests: String,
+    },
+
+    #[error("{domain} is not a valid keyserver")]
+    #[diagnostic(
+        code(E0021),
+        help(
+            "Keyservers are given by domain, e.g. `keys.openpgp.org`, or by url when they aren't served over https, e.g. `http://localhost:11371`"
+        )
+    )]
+    InvalidKeyserver { domain: String },
+
+    #[error("No key was found for {identifier} on {keyservers}")]
+    #[diagnostic(
+        code(E0022),
+        help(
+            "Every keyserver answered that it doesn't have the key, try others with `-k, --keyserver-domain`"
+        )
+    )]
+    KeyNotFound {
+        identifier: String,
+        keyservers: String,
+    },
+
+    #[error("Failed to ASCII-Armor key")]
+    #[diagnostic(code(E0006))]
+    FailedToArmorKey {
+        #[help]
+        message: String,
+    },
+}
+
+#[derive(Error, Diagnostic, Debug)]
+#[error("Calling keyoxide without a subcommand is deprecated")]
+#[diagnostic(
+    code(W0001),
+    severity(Warning),
+    help(
+        "Use `keyoxide verify <DOIP_PROFILE_URI>` or `keyoxide verify -i <INPUT_KEY_FILE>` instead"
+    )
+)]
+pub struct DeprecatedInvocation;
+
+#[derive(Error, Diagnostic, Debug)]
+#[error(
+    "No UserID of key {fingerprint} holds {email_address}, the address it was looked up with over WKD"
+)]
+#[diagnostic(
+    code(W0004),
+    severity(Warning),
+    help("WKD should only serve keys with a UserID holding the address they are served for")
+)]
+pub struct WkdUserIDMismatch {
+    pub fingerprint: String,
+    pub email_address: String,
+}
+
+#[derive(Error, Diagnostic, Debug)]
+#[error("{subject} is {state}, its proofs can't be relied on")]
+#[diagnostic(
+    code(W0005),
+    severity(Warning),
+    help(
+        "Pass `--strict` to leave the proofs of revoked, expired or invalid keys and UserIDs unverified"
+    )
+)]
+pub struct NotValid {
+    /// `Key <fingerprint>` or `UserID <userid>`
+    pub subject: String,
+    pub state: &'static str,
 }
 
 #[derive(Error, Diagnostic, Debug)]
@@ -66,18 +277,7 @@ pub struct ProofError {
 
 impl ProofError {
     pub fn warn_proof_errors(&self) {
-        match env::var_os("RUST_LOG") {
-            Some(rust_log) => {
-                if !rust_log.eq_ignore_ascii_case("off") {
-                    writeln!(stderr(), "{}", DisplayDiagnostic(self))
-                        .into_diagnostic()
-                        .unwrap()
-                }
-            }
-            _ => writeln!(stderr(), "{}", DisplayDiagnostic(self))
-                .into_diagnostic()
-                .unwrap(),
-        }
+        warn(self)
     }
 
     pub fn from(proof_uri: String, service_uri: String, doip_error: DoipError) -> Self {
@@ -92,6 +292,22 @@ impl ProofError {
     }
 }
 
+/// Writes a warning diagnostic to stderr unless `RUST_LOG` is set to `off`
+pub fn warn(diagnostic: &dyn miette::Diagnostic) {
+    match env::var_os("RUST_LOG") {
+        Some(rust_log) => {
+            if !rust_log.eq_ignore_ascii_case("off") {
+                writeln!(stderr(), "{}", DisplayDiagnostic(diagnostic))
+                    .into_diagnostic()
+                    .unwrap()
+            }
+        }
+        _ => writeln!(stderr(), "{}", DisplayDiagnostic(diagnostic))
+            .into_diagnostic()
+            .unwrap(),
+    }
+}
+
 struct DisplayDiagnostic<'a>(&'a dyn miette::Diagnostic);
 impl fmt::Display for DisplayDiagnostic<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -0,0 +1,455 @@
+use std::time::SystemTime;
+
+use super::{
+    doip::{PrintFormat, Printable},
+    openpgp::{doip_proofs, user_id_to_user_id_string},
+    validity::standard_policy,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use display_json::{DebugAsJsonPretty, DisplayAsJson};
+use miette::Result;
+use sequoia_openpgp::{
+    Cert,
+    cert::amalgamation::{ValidAmalgamation, ValidateAmalgamation},
+    crypto::mpi::PublicKey,
+    packet::{Key, Signature, UserID, key},
+    types::{KeyFlags, RevocationStatus},
+};
+use serde::Serialize;
+
+#[derive(Serialize, DisplayAsJson, DebugAsJsonPretty)]
+pub struct CertInspection {
+    fingerprint: String,
+    proof_uri: String,
+    primary_key: KeyDetails,
+    /// Whether the cert is revoked as a whole
+    revocation: Revocation,
+    subkeys: Vec<KeyDetails>,
+    userids: Vec<UserIDDetails>,
+}
+
+/// Algorithm, lifetime and capabilities of the primary key or a subkey
+#[derive(Serialize)]
+pub struct KeyDetails {
+    pub fingerprint: String,
+    pub algorithm: String,
+    /// Curve of ECC keys
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub curve: Option<String>,
+    pub bits: Option<usize>,
+    pub created: String,
+    pub expires: Option<String>,
+    /// `certify`, `sign`, `encrypt-transport`, `encrypt-storage` or `authenticate`, empty when
+    /// the key has no valid binding signature
+    pub capabilities: Vec<&'static str>,
+    pub revocation: Revocation,
+}
+
+#[derive(Serialize)]
+pub struct UserIDDetails {
+    pub userid: String,
+    /// Whether this is the primary UserID of the cert
+    pub primary: bool,
+    /// Creation time of every self-signature, oldest first
+    pub self_signatures: Vec<String>,
+    pub revocation: Revocation,
+    /// Certifications made by other keys
+    pub certifications: Vec<Certification>,
+    /// Claims found in the notations of the UserID as of the time it is inspected at, not verified
+    pub claims: Vec<String>,
+}
+
+/// A third-party certification of a UserID
+#[derive(Serialize)]
+pub struct Certification {
+    /// Fingerprint or key id of the key that made it, as the signature claims
+    pub issuer: Option<String>,
+    pub created: Option<String>,
+    /// Signature type, e.g. `PositiveCertification`
+    pub kind: String,
+    pub status: CertificationStatus,
+}
+
+/// Whether a certification was checked against the key of its issuer
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CertificationStatus {
+    /// The issuer's key made the signature
+    Verified,
+    /// The issuer's key is known but didn't make the signature
+    Invalid,
+    /// The issuer's key isn't known, nothing about the certification can be trusted
+    Unverified,
+}
+
+impl CertificationStatus {
+    fn text(&self) -> &'static str {
+        match self {
+            CertificationStatus::Verified => "verified",
+            CertificationStatus::Invalid => "INVALID",
+            CertificationStatus::Unverified => "unverified, issuer key not available",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Revocation {
+    NotRevoked,
+    Revoked,
+    /// A third party revoked it, which only counts if it is a designated revoker
+    CouldBeRevoked,
+    /// There is no valid self-signature to tell from
+    Unknown,
+}
+
+impl From<RevocationStatus<'_>> for Revocation {
+    fn from(revocation_status: RevocationStatus<'_>) -> Self {
+        match revocation_status {
+            RevocationStatus::Revoked(_) => Revocation::Revoked,
+            RevocationStatus::CouldBe(_) => Revocation::CouldBeRevoked,
+            RevocationStatus::NotAsFarAsWeKnow => Revocation::NotRevoked,
+        }
+    }
+}
+
+impl Revocation {
+    fn text(&self) -> &'static str {
+        match self {
+            Revocation::NotRevoked => "not revoked",
+            Revocation::Revoked => "REVOKED",
+            Revocation::CouldBeRevoked => "possibly revoked by a third party",
+            Revocation::Unknown => "unknown",
+        }
+    }
+}
+
+impl Printable for CertInspection {
+    fn print(&self, print_format: &PrintFormat) {
+        match print_format {
+            PrintFormat::Json | PrintFormat::Ndjson => println!("{self}"),
+            PrintFormat::JsonPretty => println!("{self:?}"),
+            PrintFormat::Text => {
+                let mut print = String::new();
+                print.push_str(format!("OpenPGP Key Fingerprint: {}\n", self.fingerprint).as_str());
+                print.push_str(format!("  Revocation: {}\n", self.revocation.text()).as_str());
+                print.push_str(key_text("Primary key", &self.primary_key, false).as_str());
+                for subkey in &self.subkeys {
+                    print.push_str(key_text("Subkey", subkey, true).as_str());
+                }
+
+                for userid in &self.userids {
+                    let primary = match userid.primary {
+                        true => " (primary)",
+                        false => "",
+                    };
+                    print.push_str(format!("  UserID: {}{primary}\n", userid.userid).as_str());
+                    print.push_str(
+                        format!("    Revocation: {}\n", userid.revocation.text()).as_str(),
+                    );
+                    print.push_str(
+                        format!(
+                            "    Self-signatures: {}\n",
+                            userid.self_signatures.join(", ")
+                        )
+                        .as_str(),
+                    );
+                    for certification in &userid.certifications {
+                        print.push_str(
+                            format!(
+                                "    Certified by {} on {} ({}, {})\n",
+                                certification.issuer.as_deref().unwrap_or("unknown"),
+                                certification.created.as_deref().unwrap_or("unknown"),
+                                certification.kind,
+                                certification.status.text()
+                            )
+                            .as_str(),
+                        );
+                    }
+                    for claim in &userid.claims {
+                        print.push_str(format!("    {}\n", claim).as_str());
+                    }
+                }
+
+                print!("{}", print);
+            }
+        }
+    }
+}
+
+fn key_text(label: &str, key: &KeyDetails, show_fingerprint: bool) -> String {
+    let size = match (&key.curve, key.bits) {
+        (Some(curve), _) => format!(" {curve}"),
+        (None, Some(bits)) => format!(" {bits} bits"),
+        (None, None) => String::new(),
+    };
+    let mut print = format!("  {label}: {}{size}\n", key.algorithm);
+    if show_fingerprint {
+        print.push_str(format!("    Fingerprint: {}\n", key.fingerprint).as_str());
+    }
+    print.push_str(format!("    Created: {}\n", key.created).as_str());
+    print.push_str(
+        format!(
+            "    Expires: {}\n",
+            key.expires.as_deref().unwrap_or("never")
+        )
+        .as_str(),
+    );
+    print.push_str(format!("    Capabilities: {}\n", key.capabilities.join(", ")).as_str());
+    print.push_str(format!("    Revocation: {}\n", key.revocation.text()).as_str());
+    print
+}
+
+impl CertInspection {
+    /// Inspects `cert` under the standard policy as of `time`, now when there is no `time`,
+    /// without verifying any claim. Third-party certifications are checked against the keys
+    /// of `issuers` that made them
+    pub fn new(cert: &Cert, issuers: &[Cert], time: Option<SystemTime>) -> Result<Self> {
+        let policy = standard_policy(time);
+        let doip_proofs = doip_proofs(cert, time);
+        let valid_cert = cert.with_policy(&policy, time).ok();
+
+        let primary_key = match valid_cert.as_ref() {
+            Some(valid_cert) => {
+                let valid_key = valid_cert.primary_key();
+                KeyDetails::new(
+                    valid_key.key().role_as_unspecified(),
+                    valid_key.key_expiration_time(),
+                    valid_key.key_flags(),
+                    valid_key.revocation_status().into(),
+                )
+            }
+            None => KeyDetails::new(
+                cert.primary_key().key().role_as_unspecified(),
+                None,
+                None,
+                Revocation::Unknown,
+            ),
+        };
+
+        let subkeys = cert
+            .keys()
+            .subkeys()
+            .map(|subkey| match subkey.clone().with_policy(&policy, time) {
+                Ok(valid_subkey) => KeyDetails::new(
+                    subkey.key().role_as_unspecified(),
+                    valid_subkey.key_expiration_time(),
+                    valid_subkey.key_flags(),
+                    valid_subkey.revocation_status().into(),
+                ),
+                Err(_) => KeyDetails::new(
+                    subkey.key().role_as_unspecified(),
+                    None,
+                    None,
+                    Revocation::Unknown,
+                ),
+            })
+            .collect();
+
+        let primary_userid = valid_cert
+            .as_ref()
+            .and_then(|valid_cert| valid_cert.primary_userid().ok())
+            .map(|primary_userid| primary_userid.userid().clone());
+
+        let userids = cert
+            .userids()
+            .map(|userid| {
+                let revocation = match userid.clone().with_policy(&policy, time) {
+                    Ok(valid_userid) => valid_userid.revocation_status().into(),
+                    Err(_) => Revocation::Unknown,
+                };
+                let mut self_signatures: Vec<SystemTime> = userid
+                    .self_signatures()
+                    .filter_map(|signature| signature.signature_creation_time())
+                    .collect();
+                self_signatures.sort();
+                let claims = doip_proofs
+                    .iter()
+                    .find(|(user_id, _)| user_id == userid.userid())
+                    .map(|(_, proofs)| proofs.iter().map(|proof| proof.uri.clone()).collect())
+                    .unwrap_or_default();
+
+                UserIDDetails {
+                    userid: user_id_to_user_id_string(userid.userid().clone()),
+                    primary: primary_userid.as_ref() == Some(userid.userid()),
+                    self_signatures: self_signatures.into_iter().map(format_time).collect(),
+                    revocation,
+                    certifications: userid
+                        .certifications()
+                        .map(|signature| {
+                            Certification::new(
+                                signature,
+                                cert.primary_key().key(),
+                                userid.userid(),
+                                issuers,
+                            )
+                        })
+                        .collect(),
+                    claims,
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            fingerprint: cert.fingerprint().to_hex(),
+            proof_uri: format!("openpgp4fpr:{}", cert.fingerprint().to_hex()),
+            primary_key,
+            revocation: cert.revocation_status(&policy, time).into(),
+            subkeys,
+            userids,
+        })
+    }
+}
+
+impl KeyDetails {
+    fn new(
+        key: &Key<key::PublicParts, key::UnspecifiedRole>,
+        expiration_time: Option<SystemTime>,
+        key_flags: Option<KeyFlags>,
+        revocation: Revocation,
+    ) -> Self {
+        let curve = match key.mpis() {
+            PublicKey::EdDSA { curve, .. }
+            | PublicKey::ECDSA { curve, .. }
+            | PublicKey::ECDH { curve, .. } => Some(curve.to_string()),
+            _ => None,
+        };
+
+        KeyDetails {
+            fingerprint: key.fingerprint().to_hex(),
+            algorithm: key.pk_algo().to_string(),
+            curve,
+            bits: key.mpis().bits(),
+            created: format_time(key.creation_time()),
+            expires: expiration_time.map(format_time),
+            capabilities: key_flags.as_ref().map(capabilities).unwrap_or_default(),
+            revocation,
+        }
+    }
+}
+
+impl Certification {
+    /// Certification `signature` of `userid` on the cert of primary key `primary_key`, checked
+    /// against every key of `issuers` the signature claims to be made by
+    fn new(
+        signature: &Signature,
+        primary_key: &Key<key::PublicParts, key::PrimaryRole>,
+        userid: &UserID,
+        issuers: &[Cert],
+    ) -> Self {
+        let mut status = CertificationStatus::Unverified;
+        let issuer_handles = signature.get_issuers();
+        let issuer_keys = issuers
+            .iter()
+            .flat_map(|issuer| issuer.keys().key_handles(issuer_handles.iter()));
+        for issuer_key in issuer_keys {
+            match signature.verify_userid_binding(issuer_key.key(), primary_key, userid) {
+                Ok(()) => {
+                    status = CertificationStatus::Verified;
+                    break;
+                }
+                Err(_) => status = CertificationStatus::Invalid,
+            }
+        }
+
+        Certification {
+            issuer: signature
+                .get_issuers()
+                .first()
+                .map(|issuer| issuer.to_hex()),
+            created: signature.signature_creation_time().map(format_time),
+            kind: signature.typ().to_string(),
+            status,
+        }
+    }
+}
+
+fn capabilities(key_flags: &KeyFlags) -> Vec<&'static str> {
+    [
+        (key_flags.for_certification(), "certify"),
+        (key_flags.for_signing(), "sign"),
+        (key_flags.for_transport_encryption(), "encrypt-transport"),
+        (key_flags.for_storage_encryption(), "encrypt-storage"),
+        (key_flags.for_authentication(), "authenticate"),
+    ]
+    .into_iter()
+    .filter_map(|(capable, capability)| capable.then_some(capability))
+    .collect()
+}
+
+/// RFC 3339 timestamp in UTC, to the second
+pub fn format_time(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sequoia_openpgp::{cert::CertBuilder, parse::Parse, types::SignatureType};
+
+    #[test]
+    fn inspect_cert() {
+        let cert = Cert::from_file("__tests__/data/TEST_DOIP_PUBLIC_KEY.asc").unwrap();
+        let inspection = CertInspection::new(&cert, &[], None).unwrap();
+        assert_eq!(inspection.revocation, Revocation::NotRevoked);
+        assert!(inspection.primary_key.capabilities.contains(&"certify"));
+        assert!(inspection.primary_key.created.ends_with('Z'));
+        assert!(inspection.userids.iter().any(|userid| userid.primary));
+        assert!(
+            inspection
+                .userids
+                .iter()
+                .all(|userid| !userid.self_signatures.is_empty())
+        );
+    }
+
+    #[test]
+    fn certifications_are_checked_against_their_issuer() {
+        let (alice, _) = CertBuilder::new()
+            .add_userid("alice@example.org")
+            .generate()
+            .unwrap();
+        let (bob, _) = CertBuilder::new()
+            .add_userid("bob@example.org")
+            .generate()
+            .unwrap();
+        let (carol, _) = CertBuilder::new()
+            .add_userid("carol@example.org")
+            .generate()
+            .unwrap();
+        let mut alice_signer = alice
+            .primary_key()
+            .key()
+            .clone()
+            .parts_into_secret()
+            .unwrap()
+            .into_keypair()
+            .unwrap();
+        let bob_userid = bob.userids().next().unwrap().userid().clone();
+        let signature = bob_userid
+            .certify(
+                &mut alice_signer,
+                &bob,
+                SignatureType::GenericCertification,
+                None,
+                None,
+            )
+            .unwrap();
+        let certification = |userid: &UserID, issuers: &[Cert]| {
+            Certification::new(&signature, bob.primary_key().key(), userid, issuers).status
+        };
+
+        assert_eq!(
+            certification(&bob_userid, &[carol.clone(), alice.clone()]),
+            CertificationStatus::Verified
+        );
+        assert_eq!(
+            certification(&bob_userid, &[carol]),
+            CertificationStatus::Unverified
+        );
+        assert_eq!(
+            certification(&UserID::from("mallory@example.org"), &[alice]),
+            CertificationStatus::Invalid
+        );
+    }
+}
@@ -0,0 +1,324 @@
+use super::{error::AppError, openpgp::read_certs_from_bytes, retry::RetryPolicy, wkd::WkdLookup};
+use futures::future::join_all;
+use miette::Result;
+use reqwest::{Client, StatusCode, Url};
+use sequoia_openpgp::Cert;
+
+pub const DEFAULT_KEYSERVER: &str = "keys.openpgp.org";
+
+/// How several keyservers are queried
+#[derive(clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum KeyserverStrategy {
+    /// Query keyservers in order until one returns a key
+    #[default]
+    Fallback,
+    /// Query every keyserver at once and merge what they return
+    Parallel,
+}
+
+impl KeyserverStrategy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyserverStrategy::Fallback => "fallback",
+            KeyserverStrategy::Parallel => "parallel",
+        }
+    }
+}
+
+/// Ordered list of keyservers used for hkp lookups, keys.openpgp.org when empty
+#[derive(Debug, Default, Clone)]
+pub struct Keyservers {
+    pub domains: Vec<String>,
+    pub strategy: KeyserverStrategy,
+}
+
+/// A cert along with where it came from
+#[derive(Debug, Clone)]
+pub struct FetchedCert {
+    pub cert: Cert,
+    /// Keyservers that returned the cert, empty when it didn't come from one
+    pub keyservers: Vec<String>,
+    /// How the cert was looked up over WKD, when it was
+    pub wkd: Option<WkdLookup>,
+}
+
+impl FetchedCert {
+    pub fn new(cert: Cert) -> Self {
+        FetchedCert {
+            cert,
+            keyservers: Vec::new(),
+            wkd: None,
+        }
+    }
+}
+
+impl Keyservers {
+    pub fn new(domains: Vec<String>, strategy: KeyserverStrategy) -> Self {
+        Keyservers { domains, strategy }
+    }
+
+    /// Domains queried, in order
+    pub fn domains(&self) -> Vec<&str> {
+        match self.domains.is_empty() {
+            true => vec![DEFAULT_KEYSERVER],
+            false => self.domains.iter().map(String::as_str).collect(),
+        }
+    }
+
+    /// Looks `identifier` up according to the strategy, merging certs returned by several
+    /// keyservers by fingerprint. Fails with the first error when no keyserver returned a key,
+    /// or with `KeyNotFound` when none of them has it
+    pub async fn fetch(
+        &self,
+        identifier: &str,
+        retry_policy: &RetryPolicy,
+    ) -> Result<Vec<FetchedCert>> {
+        let domains = self.domains();
+        let mut fetched_certs = Vec::new();
+        let mut first_error = None;
+
+        match self.strategy {
+            KeyserverStrategy::Fallback => {
+                for domain in domains {
+                    match fetch_from(identifier, domain, retry_policy).await {
+                        Ok(certs) if !certs.is_empty() => {
+                            merge_certs(&mut fetched_certs, domain, certs);
+                            break;
+                        }
+                        Ok(_) => {}
+                        Err(error) => {
+                            first_error.get_or_insert(error);
+                        }
+                    }
+                }
+            }
+            KeyserverStrategy::Parallel => {
+                let results = join_all(
+                    domains
+                        .iter()
+                        .map(|domain| fetch_from(identifier, domain, retry_policy)),
+                )
+                .await;
+                for (domain, result) in domains.into_iter().zip(results) {
+                    match result {
+                        Ok(certs) => merge_certs(&mut fetched_certs, domain, certs),
+                        Err(error) => {
+                            first_error.get_or_insert(error);
+                        }
+                    }
+                }
+            }
+        }
+
+        match (fetched_certs.is_empty(), first_error) {
+            (true, Some(error)) => Err(error),
+            (true, None) => Err(AppError::KeyNotFound {
+                identifier: identifier.to_string(),
+                keyservers: self.domains().join(", "),
+            }
+            .into()),
+            (false, _) => Ok(fetched_certs),
+        }
+    }
+}
+
+/// Looks `identifier` up on `domain` over HKP. Keyservers answer 404 for keys they don't
+/// have, which is a miss rather than an error
+async fn fetch_from(
+    identifier: &str,
+    domain: &str,
+    retry_policy: &RetryPolicy,
+) -> Result<Vec<Cert>> {
+    let url = lookup_url(identifier, domain).ok_or_else(|| AppError::InvalidKeyserver {
+        domain: domain.to_string(),
+    })?;
+    let client = Client::new();
+    let response = retry_policy
+        .send(|| client.get(url.clone()))
+        .await
+        .map_err(AppError::FailedToFetchKey)?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(Vec::new());
+    }
+    let key_bytes = response
+        .error_for_status()
+        .map_err(AppError::FailedToFetchKey)?
+        .bytes()
+        .await
+        .map_err(AppError::FailedToFetchKey)?;
+    read_certs_from_bytes(&key_bytes, domain)
+}
+
+/// `https://<domain>/pks/lookup?op=get&options=mr&search=<identifier>`, with fingerprints and
+/// key ids searched for with a `0x` prefix. `domain` may also be the url of a keyserver that
+/// isn't served over https
+fn lookup_url(identifier: &str, domain: &str) -> Option<Url> {
+    let base_url = match domain.contains("://") {
+        true => domain.to_string(),
+        false => format!("https://{domain}"),
+    };
+    let search = match identifier.contains('@') || identifier.starts_with("0x") {
+        true => identifier.to_string(),
+        false => format!("0x{identifier}"),
+    };
+    let mut url = Url::parse(&base_url).ok()?.join("/pks/lookup").ok()?;
+    url.query_pairs_mut()
+        .append_pair("op", "get")
+        .append_pair("options", "mr")
+        .append_pair("search", &search);
+    Some(url)
+}
+
+/// Adds `certs` returned by `domain`, merging them into certs with the same fingerprint
+fn merge_certs(fetched_certs: &mut Vec<FetchedCert>, domain: &str, certs: Vec<Cert>) {
+    for cert in certs {
+        merge_fetched_cert(
+            fetched_certs,
+            FetchedCert {
+                cert,
+                keyservers: vec![domain.to_string()],
+                wkd: None,
+            },
+        );
+    }
+}
+
+/// Adds `fetched_cert`, merging it into the cert with the same fingerprint when there is one
+pub(crate) fn merge_fetched_cert(fetched_certs: &mut Vec<FetchedCert>, fetched_cert: FetchedCert) {
+    let existing = fetched_certs
+        .iter_mut()
+        .find(|existing| existing.cert.fingerprint() == fetched_cert.cert.fingerprint());
+    match existing {
+        Some(existing) => {
+            if let Ok(merged_cert) = existing.cert.clone().merge_public(fetched_cert.cert) {
+                existing.cert = merged_cert;
+            }
+            existing.wkd = existing.wkd.take().or(fetched_cert.wkd);
+            for keyserver in fetched_cert.keyservers {
+                if !existing.keyservers.contains(&keyserver) {
+                    existing.keyservers.push(keyserver);
+                }
+            }
+        }
+        None => fetched_certs.push(fetched_cert),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::doip::tests::{refused_url, serve};
+    use sequoia_openpgp::parse::Parse;
+
+    const TEST_DOIP_KEY_RESPONSE: &str = concat!(
+        "HTTP/1.1 200 OK\r\nconnection: close\r\n\r\n",
+        include_str!("../../__tests__/data/TEST_DOIP_PUBLIC_KEY.asc")
+    );
+    const NOT_FOUND_RESPONSE: &str =
+        "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n";
+
+    fn cert(path: &str) -> Cert {
+        Cert::from_file(path).unwrap()
+    }
+
+    #[tokio::test]
+    async fn fallback_stops_at_the_first_keyserver_with_the_key() {
+        let missing = serve(vec![NOT_FOUND_RESPONSE]);
+        let found = serve(vec![TEST_DOIP_KEY_RESPONSE]);
+        let keyservers = Keyservers::new(
+            vec![refused_url(), missing, found.clone(), refused_url()],
+            KeyserverStrategy::Fallback,
+        );
+        let fetched_certs = keyservers
+            .fetch("test@doip.rocks", &RetryPolicy::new(0))
+            .await
+            .unwrap();
+
+        assert_eq!(fetched_certs.len(), 1);
+        assert_eq!(fetched_certs[0].keyservers, [found]);
+    }
+
+    #[tokio::test]
+    async fn fails_with_the_first_error_when_every_keyserver_fails() {
+        let keyservers = Keyservers::new(
+            vec![refused_url(), serve(vec![NOT_FOUND_RESPONSE])],
+            KeyserverStrategy::Fallback,
+        );
+        let error = keyservers
+            .fetch("test@doip.rocks", &RetryPolicy::new(0))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            error.downcast_ref::<AppError>(),
+            Some(AppError::FailedToFetchKey(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn fails_when_no_keyserver_has_the_key() {
+        let keyservers = Keyservers::new(
+            vec![
+                serve(vec![NOT_FOUND_RESPONSE]),
+                serve(vec![NOT_FOUND_RESPONSE]),
+            ],
+            KeyserverStrategy::Parallel,
+        );
+        let error = keyservers
+            .fetch("test@doip.rocks", &RetryPolicy::new(0))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            error.downcast_ref::<AppError>(),
+            Some(AppError::KeyNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn merge_certs_by_fingerprint() {
+        let mut fetched_certs = Vec::new();
+        merge_certs(
+            &mut fetched_certs,
+            "keys.openpgp.org",
+            vec![cert("__tests__/data/TEST_DOIP_PUBLIC_KEY.asc")],
+        );
+        merge_certs(
+            &mut fetched_certs,
+            "keyserver.ubuntu.com",
+            vec![
+                cert("__tests__/data/TEST_DOIP_PUBLIC_KEY.gpg"),
+                cert("__tests__/data/ALEXIS_LOWE_PUBLIC_KEY.asc"),
+            ],
+        );
+
+        assert_eq!(fetched_certs.len(), 2);
+        assert_eq!(
+            fetched_certs[0].keyservers,
+            ["keys.openpgp.org", "keyserver.ubuntu.com"]
+        );
+        assert_eq!(fetched_certs[1].keyservers, ["keyserver.ubuntu.com"]);
+    }
+
+    #[test]
+    fn lookup_urls() {
+        assert_eq!(
+            lookup_url("test@doip.rocks", "keys.openpgp.org")
+                .unwrap()
+                .as_str(),
+            "https://keys.openpgp.org/pks/lookup?op=get&options=mr&search=test%40doip.rocks"
+        );
+        assert_eq!(
+            lookup_url("EF2DC5827B445F4B", "http://localhost:11371")
+                .unwrap()
+                .as_str(),
+            "http://localhost:11371/pks/lookup?op=get&options=mr&search=0xEF2DC5827B445F4B"
+        );
+        assert!(lookup_url("0xEF2DC5827B445F4B", "not a domain").is_none());
+    }
+
+    #[test]
+    fn default_keyserver() {
+        assert_eq!(Keyservers::default().domains(), [DEFAULT_KEYSERVER]);
+    }
+}
@@ -0,0 +1,190 @@
+use std::{collections::HashMap, future::Future, sync::Mutex, time::Duration};
+
+use tokio::{
+    sync::{Semaphore, SemaphorePermit},
+    time::{Instant, sleep_until, timeout_at},
+};
+
+pub const DEFAULT_MAX_CONCURRENT_PROOFS: usize = 16;
+pub const DEFAULT_PER_HOST_RATE: u32 = 4;
+pub const DEFAULT_PROOF_TIMEOUT_SECS: u64 = 30;
+
+/// Bounds how many proofs are verified at once, how often each host is hit and how long
+/// verification may take
+#[derive(Debug)]
+pub struct ProofLimiter {
+    concurrency: Semaphore,
+    per_host_interval: Option<Duration>,
+    next_slots: Mutex<HashMap<String, Instant>>,
+    proof_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+}
+
+impl ProofLimiter {
+    /// `per_host_rate` is the number of proofs per second allowed per host, `0` disables it
+    pub fn new(max_concurrent_proofs: usize, per_host_rate: u32) -> Self {
+        ProofLimiter {
+            concurrency: Semaphore::new(max_concurrent_proofs.max(1)),
+            per_host_interval: match per_host_rate {
+                0 => None,
+                rate => Some(Duration::from_secs(1) / rate),
+            },
+            next_slots: Mutex::new(HashMap::new()),
+            proof_timeout: None,
+            deadline: None,
+        }
+    }
+
+    /// Gives up on a single proof after `proof_timeout`, and on everything still outstanding
+    /// once `total_timeout` has elapsed from now
+    pub fn with_timeouts(
+        mut self,
+        proof_timeout: Option<Duration>,
+        total_timeout: Option<Duration>,
+    ) -> Self {
+        self.proof_timeout = proof_timeout;
+        self.deadline = total_timeout.map(|total_timeout| Instant::now() + total_timeout);
+        self
+    }
+
+    /// When the total timeout elapses, `None` if there is none
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// When a proof starting now must be verified by, the earliest of its own and the total timeout
+    pub fn proof_deadline(&self) -> Option<Instant> {
+        let proof_deadline = self
+            .proof_timeout
+            .map(|proof_timeout| Instant::now() + proof_timeout);
+        match (proof_deadline, self.deadline) {
+            (Some(proof_deadline), Some(deadline)) => Some(proof_deadline.min(deadline)),
+            (proof_deadline, deadline) => proof_deadline.or(deadline),
+        }
+    }
+
+    /// Waits for a free slot, then for the host of `service_uri` to be allowed another request.
+    /// The host slot is only reserved once the permit is held, so requests queued behind the
+    /// concurrency limit don't use up slots and then fire at once
+    pub async fn acquire(&self, service_uri: &str) -> SemaphorePermit<'_> {
+        let permit = self
+            .concurrency
+            .acquire()
+            .await
+            .expect("proof limiter semaphore is never closed");
+        if let Some(slot) = self.reserve_host_slot(&host_of(service_uri)) {
+            sleep_until(slot).await;
+        }
+        permit
+    }
+
+    fn reserve_host_slot(&self, host: &str) -> Option<Instant> {
+        let per_host_interval = self.per_host_interval?;
+        let now = Instant::now();
+        let mut next_slots = self
+            .next_slots
+            .lock()
+            .expect("proof limiter lock is never poisoned");
+        let slot = next_slots
+            .get(host)
+            .map_or(now, |next_slot| (*next_slot).max(now));
+        next_slots.insert(host.to_string(), slot + per_host_interval);
+        Some(slot)
+    }
+}
+
+impl Default for ProofLimiter {
+    fn default() -> Self {
+        ProofLimiter::new(DEFAULT_MAX_CONCURRENT_PROOFS, DEFAULT_PER_HOST_RATE)
+            .with_timeouts(Some(Duration::from_secs(DEFAULT_PROOF_TIMEOUT_SECS)), None)
+    }
+}
+
+/// Runs `future` to completion unless `deadline` passes first, `None` never times out
+pub async fn run_until<F: Future>(deadline: Option<Instant>, future: F) -> Option<F::Output> {
+    match deadline {
+        Some(deadline) => timeout_at(deadline, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+/// Host a claim's proof is fetched from, `dns:` and `xmpp:` style uris have no authority so
+/// the domain is taken from the path instead
+pub fn host_of(service_uri: &str) -> String {
+    match reqwest::Url::parse(service_uri) {
+        Ok(url) => match url.host_str() {
+            Some(host) => host.to_lowercase(),
+            None => {
+                let path = url.path();
+                let domain = path.rsplit('@').next().unwrap_or(path);
+                domain
+                    .split(['/', '?', '#'])
+                    .next()
+                    .unwrap_or(domain)
+                    .to_lowercase()
+            }
+        },
+        Err(_) => service_uri.to_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::join_all;
+    use tokio::time::sleep;
+
+    #[test]
+    fn host_of_service_uris() {
+        assert_eq!(host_of("https://github.com/chimbosonic"), "github.com");
+        assert_eq!(
+            host_of("https://Fosstodon.org/@chimbosonic"),
+            "fosstodon.org"
+        );
+        assert_eq!(host_of("dns:doip.rocks?type=TXT"), "doip.rocks");
+        assert_eq!(host_of("xmpp:alice@doip.rocks"), "doip.rocks");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn per_host_rate_spaces_out_requests() {
+        let limiter = ProofLimiter::new(4, 10);
+        let start = Instant::now();
+        for _ in 0..3 {
+            drop(limiter.acquire("https://github.com/a").await);
+        }
+        drop(limiter.acquire("https://gitlab.com/a").await);
+        assert_eq!(start.elapsed(), Duration::from_millis(200));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn queued_requests_do_not_use_up_host_slots() {
+        let limiter = ProofLimiter::new(1, 1);
+        let start = Instant::now();
+        let permit = limiter.acquire("https://github.com/a").await;
+
+        let queued = join_all((0..2).map(|_| async {
+            let _permit = limiter.acquire("https://github.com/a").await;
+            start.elapsed()
+        }));
+        let release = async {
+            sleep(Duration::from_secs(5)).await;
+            drop(permit);
+        };
+        let (acquired_at, ()) = futures::join!(queued, release);
+        assert_eq!(
+            acquired_at,
+            [Duration::from_secs(5), Duration::from_secs(6)]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn total_timeout_cancels_outstanding_futures() {
+        let limiter = ProofLimiter::new(1, 0).with_timeouts(None, Some(Duration::from_secs(50)));
+        let start = Instant::now();
+        let hung = std::future::pending::<()>();
+        assert_eq!(run_until(limiter.proof_deadline(), hung).await, None);
+        assert_eq!(start.elapsed(), Duration::from_secs(50));
+        // Futures that are ready are still taken once the deadline has passed
+        assert_eq!(run_until(limiter.deadline(), async { 1 }).await, Some(1));
+    }
+}
@@ -1,5 +1,20 @@
 pub mod aspe;
+pub mod batch;
+pub mod cache;
+pub mod claim;
 pub mod clap;
+pub mod config;
+pub mod context;
+pub mod discover;
 pub mod doip;
 pub mod error;
+pub mod inspect;
+pub mod keyserver;
+pub mod limit;
 pub mod openpgp;
+pub mod policy;
+pub mod profile;
+pub mod retry;
+pub mod uri;
+pub mod validity;
+pub mod wkd;
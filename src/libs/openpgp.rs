@@ -1,40 +1,108 @@
-use std::fs;
+use std::{
+    fs,
+    io::{self, Read},
+    time::SystemTime,
+};
 
 use super::{
-    doip::{PrintFormat, Profile, VerifiedProof, verify_proof},
+    cache::{Cache, CacheKind},
+    context::VerifyContext,
+    discover::discover_certs,
+    doip::{PrintFormat, Printable, VerifiedProof, verify_proof},
     error::AppError,
+    inspect::format_time,
+    keyserver::{FetchedCert, Keyservers},
+    limit::run_until,
+    retry::RetryPolicy,
+    uri::ProfileUri,
+    validity::{Validity, standard_policy},
+    wkd::WkdLookup,
 };
 use display_json::{DebugAsJsonPretty, DisplayAsJson};
-use doip_openpgp::openpgp::{fetch_hkp, fetch_wkd, get_keys_doip_proofs, read_key_from_string};
+use doip_openpgp::openpgp::read_key_from_string;
 use futures::future::join_all;
 use miette::Result;
-use sequoia_openpgp::{Cert, packet::UserID};
-use serde::Serialize;
+use sequoia_openpgp::{
+    Cert,
+    cert::{
+        CertParser,
+        amalgamation::{ValidAmalgamation, ValidateAmalgamation},
+    },
+    packet::{
+        Signature, UserID,
+        signature::subpacket::{SubpacketTag, SubpacketValue},
+    },
+    parse::Parse,
+    serialize::SerializeInto,
+};
+use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, DisplayAsJson, DebugAsJsonPretty)]
 pub struct KeyProfile {
-    fingerprint: String,
-    proof_uri: String,
-    userid_proofs: Vec<UserIDVerifiedProofs>,
+    pub fingerprint: String,
+    pub proof_uri: String,
+    /// Keyservers that returned the key, empty when it wasn't fetched from a keyserver
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub keyservers: Vec<String>,
+    /// How the key was looked up over WKD, when it was
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wkd: Option<WkdLookup>,
+    /// Time given with `--at` the key was evaluated at, now when there is none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub evaluated_at: Option<String>,
+    /// Whether the key is revoked or expired as of the time it was evaluated at
+    pub validity: Validity,
+    pub userid_proofs: Vec<UserIDVerifiedProofs>,
 }
 
-impl Profile for KeyProfile {
+impl Printable for KeyProfile {
     fn print(&self, print_format: &PrintFormat) {
         match print_format {
-            PrintFormat::Json => println!("{self}"),
+            PrintFormat::Json | PrintFormat::Ndjson => println!("{self}"),
             PrintFormat::JsonPretty => println!("{self:?}"),
             PrintFormat::Text => {
                 let mut print = String::new();
-                print.push_str(format!("OpenPGP Key Fingerprint: {}\n", self.fingerprint).as_str());
+                print.push_str(
+                    format!(
+                        "OpenPGP Key Fingerprint: {}{}\n",
+                        self.fingerprint,
+                        self.validity.flag()
+                    )
+                    .as_str(),
+                );
+                if let Some(evaluated_at) = &self.evaluated_at {
+                    print.push_str(format!("  Evaluated at: {evaluated_at}\n").as_str());
+                }
+                if !self.keyservers.is_empty() {
+                    print.push_str(
+                        format!("  Keyservers: {}\n", self.keyservers.join(", ")).as_str(),
+                    );
+                }
+                if let Some(wkd) = &self.wkd {
+                    print.push_str(wkd_text(wkd).as_str());
+                }
 
                 for useridproofs in &self.userid_proofs {
-                    print.push_str(format!("  UserID: {}\n", useridproofs.userid).as_str());
+                    print.push_str(
+                        format!(
+                            "  UserID: {}{}\n",
+                            useridproofs.userid,
+                            useridproofs.validity.flag()
+                        )
+                        .as_str(),
+                    );
 
                     for verified_proof in &useridproofs.proofs {
-                        if verified_proof.verification_result.is_some() {
-                            print.push_str(format!("    {}: ✅\n", verified_proof.uri).as_str());
-                        } else {
-                            print.push_str(format!("    {}: ❌\n", verified_proof.uri).as_str());
+                        print.push_str(
+                            format!(
+                                "    {}: {}\n",
+                                verified_proof.uri,
+                                verified_proof.status_text()
+                            )
+                            .as_str(),
+                        );
+                        if let Some(signature) = &verified_proof.signature {
+                            print.push_str(proof_signature_text(signature).as_str());
                         }
                     }
                 }
@@ -47,39 +115,81 @@ impl Profile for KeyProfile {
 
 #[derive(Serialize)]
 pub struct UserIDVerifiedProofs {
-    userid: String,
-    proofs: Vec<VerifiedProof>,
+    pub userid: String,
+    /// Whether the binding of the UserID to the key is revoked or expired as of the time it
+    /// was evaluated at
+    pub validity: Validity,
+    pub proofs: Vec<VerifiedProof>,
 }
 
 impl UserIDVerifiedProofs {
-    fn new(userid: String) -> UserIDVerifiedProofs {
+    fn new(userid: String, validity: Validity) -> UserIDVerifiedProofs {
         UserIDVerifiedProofs {
             userid,
+            validity,
             proofs: Vec::new(),
         }
     }
 }
 
 impl KeyProfile {
-    pub async fn new_from_hkp(key_uri: String, key_server: Option<String>) -> Result<Vec<Self>> {
-        let identifier = key_uri
-            .split_once(':')
-            .ok_or(AppError::ProfileURIMalformed)?
-            .1;
-        let certs = fetch_hkp(identifier, key_server.as_deref()).await?;
-        let key_profiles_results: Vec<Result<Self>> =
-            join_all(certs.into_iter().map(|x| async { Self::new(x).await })).await;
-        let key_profiles: Vec<Self> = key_profiles_results
-            .into_iter()
-            .collect::<Result<Vec<Self>>>()?;
+    pub async fn new_from_hkp(
+        identifier: &str,
+        keyservers: &Keyservers,
+        context: &VerifyContext,
+    ) -> Result<Vec<Self>> {
+        let profile_uri = ProfileUri::Hkp(identifier.to_string());
+        Self::new_from_uri(&profile_uri, keyservers, context).await
+    }
 
-        Ok(key_profiles)
+    pub async fn new_from_wkd(email_address: &str, context: &VerifyContext) -> Result<Vec<Self>> {
+        let profile_uri = ProfileUri::Wkd(email_address.to_string());
+        Self::new_from_uri(&profile_uri, &Keyservers::default(), context).await
     }
 
-    pub async fn new_from_wkd(key_uri: String) -> Result<Vec<Self>> {
-        let certs = fetch_wkd(&key_uri[4..]).await?;
+    pub async fn new_from_https(url: &str, context: &VerifyContext) -> Result<Vec<Self>> {
+        let profile_uri = ProfileUri::Https(url.to_string());
+        Self::new_from_uri(&profile_uri, &Keyservers::default(), context).await
+    }
+
+    /// Verifies every key of a file, which may be a keyring holding several certs
+    pub async fn new_from_file(key_path: String, context: &VerifyContext) -> Result<Vec<Self>> {
+        let certs = read_certs_from_file(key_path)?;
+        Self::new_from_certs(certs.into_iter().map(FetchedCert::new).collect(), context).await
+    }
+
+    /// Fetches the certs behind any OpenPGP profile uri and verifies their proofs
+    pub async fn new_from_uri(
+        profile_uri: &ProfileUri,
+        keyservers: &Keyservers,
+        context: &VerifyContext,
+    ) -> Result<Vec<Self>> {
+        let certs = run_until(
+            context.limiter.deadline(),
+            fetch_certs(
+                profile_uri,
+                keyservers,
+                &context.retry_policy,
+                &context.cache,
+            ),
+        )
+        .await
+        .ok_or(AppError::TotalTimeoutElapsed)??;
+        Self::new_from_certs(certs, context).await
+    }
+
+    pub(crate) async fn new_from_certs(
+        certs: Vec<FetchedCert>,
+        context: &VerifyContext,
+    ) -> Result<Vec<Self>> {
         let key_profiles_results: Vec<Result<Self>> =
-            join_all(certs.into_iter().map(|x| async { Self::new(x).await })).await;
+            join_all(certs.into_iter().map(|fetched_cert| async move {
+                let mut key_profile = Self::new(fetched_cert.cert, context).await?;
+                key_profile.keyservers = fetched_cert.keyservers;
+                key_profile.wkd = fetched_cert.wkd;
+                Ok::<Self, miette::Report>(key_profile)
+            }))
+            .await;
         let key_profiles: Vec<Self> = key_profiles_results
             .into_iter()
             .collect::<Result<Vec<Self>>>()?;
@@ -87,55 +197,476 @@ impl KeyProfile {
         Ok(key_profiles)
     }
 
-    pub async fn new_from_file(key_path: String) -> Result<Self> {
-        let file_contents: Result<String> = match fs::read_to_string(key_path) {
-            Ok(s) => Ok(s),
-            Err(error) => Err(AppError::FailedToReadKeyFile(error).into()),
-        };
-        let cert = read_key_from_string(&file_contents?)?;
-        Self::new(cert).await
-    }
-
-    pub async fn new(cert: Cert) -> Result<Self> {
-        #[allow(clippy::mutable_key_type)]
-        let doip_proofs = get_keys_doip_proofs(&cert)?;
+    /// Verifies every proof of `cert`, each waiting for the limiter of `context` before being
+    /// fetched. The key and its UserIDs are evaluated under the standard policy at the reference
+    /// time of `context`, with `--strict` the proofs of those that are revoked, expired or invalid
+    /// aren't verified. Proofs read from a self-signature that isn't the current binding
+    /// signature are never verified
+    pub async fn new(cert: Cert, context: &VerifyContext) -> Result<Self> {
+        let reference_time = context.reference_time;
+        let doip_proofs = doip_proofs(&cert, reference_time);
 
         let mut key_verified_proofs = KeyProfile {
             fingerprint: cert.fingerprint().to_hex(),
             proof_uri: format!("openpgp4fpr:{}", cert.fingerprint().to_hex()),
+            keyservers: Vec::new(),
+            wkd: None,
+            evaluated_at: reference_time.map(format_time),
+            validity: Validity::of_cert(&cert, reference_time),
             userid_proofs: Vec::new(),
         };
+        key_verified_proofs
+            .validity
+            .warn(|| format!("Key {}", key_verified_proofs.fingerprint));
 
         for (user_id, proofs) in doip_proofs {
-            let user_id_string = user_id_to_user_id_string(user_id);
-
-            let mut proofs_futures = Vec::new();
-
-            for service_uri in proofs {
-                let verification_result =
-                    verify_proof(service_uri, key_verified_proofs.proof_uri.clone());
-                proofs_futures.push(verification_result);
-            }
+            let user_id_string = user_id_to_user_id_string(user_id.clone());
+            let validity = match key_verified_proofs.validity {
+                Validity::Valid => {
+                    let validity = Validity::of_userid(&cert, &user_id, reference_time);
+                    validity.warn(|| format!("UserID {user_id_string}"));
+                    validity
+                }
+                key_validity => key_validity,
+            };
 
-            let proofs = join_all(proofs_futures)
-                .await
-                .into_iter()
-                .map(|(s, r)| VerifiedProof::new(s, r))
-                .collect();
+            // Proofs read from a signature that isn't the current binding signature aren't
+            // vouched for by the key, they are reported but never fetched
+            let skip_invalid = context.strict && !validity.is_valid();
+            let proof_uri = &key_verified_proofs.proof_uri;
+            let proofs: Vec<VerifiedProof> = join_all(proofs.into_iter().map(|proof| async move {
+                let verified_proof = match skip_invalid || !proof.signature.current {
+                    true => VerifiedProof::invalid_key(proof.uri),
+                    false => verify_proof(proof.uri, proof_uri.clone(), context).await,
+                };
+                verified_proof.with_signature(proof.signature)
+            }))
+            .await;
 
-            let mut verified_proofs = UserIDVerifiedProofs::new(user_id_string);
+            let mut verified_proofs = UserIDVerifiedProofs::new(user_id_string, validity);
             verified_proofs.proofs = proofs;
             key_verified_proofs.add_userid_proofs(verified_proofs);
         }
         Ok(key_verified_proofs)
     }
 
+    /// Every proof of every UserID of the key
+    pub fn proofs(&self) -> impl Iterator<Item = &VerifiedProof> {
+        self.userid_proofs
+            .iter()
+            .flat_map(|userid_proofs| userid_proofs.proofs.iter())
+    }
+
     fn add_userid_proofs(&mut self, proofs: UserIDVerifiedProofs) {
         self.userid_proofs.push(proofs)
     }
 }
 
-fn user_id_to_user_id_string(user_id: UserID) -> String {
+/// Names of the notations DOIP proofs are stored in, the second one being the legacy name
+const DOIP_PROOF_NOTATIONS: [&str; 2] = ["proof@ariadne.id", "proof@metacode.biz"];
+
+/// Self-signature a proof was read from
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProofSignature {
+    /// Creation time of the self-signature
+    pub created: Option<String>,
+    pub hash_algorithm: String,
+    /// Whether the notation subpacket is marked critical
+    pub critical: bool,
+    /// Whether the notation is flagged as human-readable
+    pub human_readable: bool,
+    /// Whether the self-signature is the binding signature of the UserID as of the time the key
+    /// was evaluated at, rather than the newest one left when there is none
+    pub current: bool,
+}
+
+/// A proof read from the notations of a self-signature
+pub(crate) struct NotationProof {
+    pub uri: String,
+    pub signature: ProofSignature,
+}
+
+/// Proofs held by every UserID of `cert` as of `time`, now when there is no `time`, read from
+/// the binding signature valid then. UserIDs that have none, e.g. because they expired, fall
+/// back to their newest self-signature made by then so their proofs can still be reported, but
+/// the policy rejects that signature so these proofs must not be verified
+pub(crate) fn doip_proofs(
+    cert: &Cert,
+    time: Option<SystemTime>,
+) -> Vec<(UserID, Vec<NotationProof>)> {
+    let policy = standard_policy(time);
+    let reference_time = time.unwrap_or_else(SystemTime::now);
+    cert.userids()
+        .filter_map(|userid| {
+            let (binding_signature, current) = match userid.clone().with_policy(&policy, time) {
+                Ok(valid_userid) => (valid_userid.binding_signature(), true),
+                Err(_) => (
+                    userid
+                        .self_signatures()
+                        .filter(|signature| {
+                            signature
+                                .signature_creation_time()
+                                .is_some_and(|creation_time| creation_time <= reference_time)
+                        })
+                        .max_by_key(|signature| signature.signature_creation_time())?,
+                    false,
+                ),
+            };
+            let proofs = notation_proofs(binding_signature, current);
+            (!proofs.is_empty()).then(|| (userid.userid().clone(), proofs))
+        })
+        .collect()
+}
+
+/// DOIP proof notations in the hashed area of `signature`, skipping duplicates
+fn notation_proofs(signature: &Signature, current: bool) -> Vec<NotationProof> {
+    let created = signature.signature_creation_time().map(format_time);
+    let mut proofs: Vec<NotationProof> = Vec::new();
+    for subpacket in signature
+        .hashed_area()
+        .subpackets(SubpacketTag::NotationData)
+    {
+        let SubpacketValue::NotationData(notation) = subpacket.value() else {
+            continue;
+        };
+        if !DOIP_PROOF_NOTATIONS.contains(&notation.name()) {
+            continue;
+        }
+        let Ok(uri) = String::from_utf8(notation.value().to_vec()) else {
+            continue;
+        };
+        if proofs.iter().any(|proof| proof.uri == uri) {
+            continue;
+        }
+        proofs.push(NotationProof {
+            uri,
+            signature: ProofSignature {
+                created: created.clone(),
+                hash_algorithm: signature.hash_algo().to_string(),
+                critical: subpacket.critical(),
+                human_readable: notation.flags().human_readable(),
+                current,
+            },
+        });
+    }
+    proofs
+}
+
+/// Cert as stored in the cache, along with where it came from
+#[derive(Serialize, Deserialize)]
+struct CachedCert {
+    armored: String,
+    keyservers: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    wkd: Option<WkdLookup>,
+}
+
+/// Fetches the certs behind an OpenPGP profile uri without verifying any of their proofs,
+/// retrying transient failures according to `retry_policy`. hkp lookups go to `keyservers`.
+/// Certs fetched over the network are served from `cache` while they are fresh
+pub async fn fetch_certs(
+    profile_uri: &ProfileUri,
+    keyservers: &Keyservers,
+    retry_policy: &RetryPolicy,
+    cache: &Cache,
+) -> Result<Vec<FetchedCert>> {
+    if let ProfileUri::File(key_path) = profile_uri {
+        let certs = read_certs_from_file(key_path.to_string())?;
+        return Ok(certs.into_iter().map(FetchedCert::new).collect());
+    }
+
+    let cache_key = match keyservers.domains.is_empty() {
+        true => profile_uri.to_string(),
+        false => format!(
+            "{profile_uri} {} {}",
+            keyservers.domains.join(","),
+            keyservers.strategy.name()
+        ),
+    };
+    let cached_certs = cache
+        .get::<Vec<CachedCert>>(CacheKind::Cert, &cache_key)
+        .filter(|cached_certs| !cached_certs.is_empty())
+        .and_then(|cached_certs| {
+            cached_certs
+                .into_iter()
+                .map(|cached_cert| {
+                    Ok(FetchedCert {
+                        cert: Cert::from_bytes(cached_cert.armored.as_bytes())?,
+                        keyservers: cached_cert.keyservers,
+                        wkd: cached_cert.wkd,
+                    })
+                })
+                .collect::<sequoia_openpgp::Result<Vec<FetchedCert>>>()
+                .ok()
+        });
+    if let Some(certs) = cached_certs {
+        return Ok(certs);
+    }
+    if cache.is_offline() {
+        return Err(AppError::NotAvailableOffline {
+            uri: profile_uri.to_string(),
+        }
+        .into());
+    }
+
+    let certs = fetch_certs_uncached(profile_uri, keyservers, retry_policy).await?;
+    let cached_certs = certs
+        .iter()
+        .map(|fetched_cert| {
+            Ok(CachedCert {
+                armored: armor_cert(&fetched_cert.cert)?,
+                keyservers: fetched_cert.keyservers.clone(),
+                wkd: fetched_cert.wkd.clone(),
+            })
+        })
+        .collect::<Result<Vec<CachedCert>>>()?;
+    // A miss may be fixed by uploading the key, it is looked up again on the next run
+    if !cached_certs.is_empty() {
+        cache.put(CacheKind::Cert, &cache_key, &cached_certs);
+    }
+    Ok(certs)
+}
+
+async fn fetch_certs_uncached(
+    profile_uri: &ProfileUri,
+    keyservers: &Keyservers,
+    retry_policy: &RetryPolicy,
+) -> Result<Vec<FetchedCert>> {
+    let certs = match profile_uri {
+        ProfileUri::Hkp(identifier) | ProfileUri::Hkps(identifier) => {
+            return keyservers.fetch(identifier, retry_policy).await;
+        }
+        ProfileUri::Openpgp4fpr {
+            fingerprint,
+            email_address,
+        } => {
+            return fetch_openpgp4fpr(
+                fingerprint,
+                email_address.as_deref(),
+                keyservers,
+                retry_policy,
+            )
+            .await;
+        }
+        ProfileUri::Email(email_address) => {
+            return discover_certs(email_address, keyservers, retry_policy).await;
+        }
+        ProfileUri::Wkd(email_address) => return fetch_wkd(email_address, retry_policy).await,
+        ProfileUri::Https(url) => fetch_https(url, retry_policy).await?,
+        ProfileUri::File(key_path) => read_certs_from_file(key_path.to_string())?,
+        ProfileUri::Aspe { .. } => {
+            return Err(AppError::ExpectedKeyUri {
+                uri: profile_uri.to_string(),
+            }
+            .into());
+        }
+    };
+    Ok(certs.into_iter().map(FetchedCert::new).collect())
+}
+
+/// Resolves a fingerprint on the keyservers, then tries WKD for every email address of the
+/// cert found and `email_address` and merges in any matching cert, as keyservers may strip
+/// UserIDs WKD still serves. When the keyservers miss, the cert is taken from WKD alone. Fails
+/// with the keyserver error when neither found the key
+pub async fn fetch_openpgp4fpr(
+    fingerprint: &str,
+    email_address: Option<&str>,
+    keyservers: &Keyservers,
+    retry_policy: &RetryPolicy,
+) -> Result<Vec<FetchedCert>> {
+    let is_match = |cert: &Cert| {
+        cert.fingerprint()
+            .to_hex()
+            .eq_ignore_ascii_case(fingerprint)
+    };
+    let (fetched_certs, keyserver_error) = match keyservers.fetch(fingerprint, retry_policy).await {
+        Ok(fetched_certs) => (fetched_certs, None),
+        Err(error) => match error.downcast_ref::<AppError>() {
+            Some(AppError::KeyNotFound { .. }) => (Vec::new(), None),
+            _ => (Vec::new(), Some(error)),
+        },
+    };
+    let mut resolved_certs: Vec<FetchedCert> = fetched_certs
+        .into_iter()
+        .filter(|fetched_cert| is_match(&fetched_cert.cert))
+        .collect();
+
+    let mut email_addresses: Vec<String> = resolved_certs
+        .iter()
+        .flat_map(|fetched_cert| cert_email_addresses(&fetched_cert.cert))
+        .chain(email_address.map(str::to_string))
+        .collect();
+    email_addresses.sort();
+    email_addresses.dedup();
+
+    for email_address in email_addresses {
+        let (wkd_certs, wkd_lookup) = WkdLookup::new(&email_address, retry_policy).await;
+        for wkd_cert in wkd_certs.into_iter().filter(is_match) {
+            match resolved_certs
+                .iter_mut()
+                .find(|fetched_cert| fetched_cert.cert.fingerprint() == wkd_cert.fingerprint())
+            {
+                Some(fetched_cert) => {
+                    if let Ok(merged_cert) = fetched_cert.cert.clone().merge_public(wkd_cert) {
+                        fetched_cert.cert = merged_cert;
+                    }
+                }
+                None => resolved_certs.push(FetchedCert {
+                    wkd: Some(wkd_lookup.for_cert(&wkd_cert)),
+                    cert: wkd_cert,
+                    keyservers: Vec::new(),
+                }),
+            }
+        }
+    }
+
+    match (resolved_certs.is_empty(), keyserver_error) {
+        (true, Some(error)) => Err(error),
+        (true, None) => Err(AppError::Openpgp4fprNotFound {
+            fingerprint: fingerprint.to_string(),
+        }
+        .into()),
+        (false, _) => Ok(resolved_certs),
+    }
+}
+
+/// Looks the key of `email_address` up over WKD, recording how it was found on every cert
+pub async fn fetch_wkd(
+    email_address: &str,
+    retry_policy: &RetryPolicy,
+) -> Result<Vec<FetchedCert>> {
+    let (certs, wkd_lookup) = WkdLookup::new(email_address, retry_policy).await;
+    if certs.is_empty() {
+        return Err(AppError::WkdKeyNotFound {
+            email_address: email_address.to_string(),
+            requests: wkd_lookup.requests_summary(),
+        }
+        .into());
+    }
+    Ok(certs
+        .into_iter()
+        .map(|cert| FetchedCert {
+            wkd: Some(wkd_lookup.for_cert(&cert)),
+            cert,
+            keyservers: Vec::new(),
+        })
+        .collect())
+}
+
+/// Self-signature a proof was read from for text output
+fn proof_signature_text(signature: &ProofSignature) -> String {
+    let mut details = vec![
+        signature.created.as_deref().unwrap_or("unknown time"),
+        signature.hash_algorithm.as_str(),
+    ];
+    if signature.critical {
+        details.push("critical");
+    }
+    if signature.human_readable {
+        details.push("human-readable");
+    }
+    let current = match signature.current {
+        true => "",
+        false => " ⚠️ not the current binding signature",
+    };
+    format!("      Self-signature: {}{current}\n", details.join(", "))
+}
+
+/// WKD method, requests, policy and warnings of a key for text output
+fn wkd_text(wkd: &WkdLookup) -> String {
+    let mut print = String::new();
+    let method = wkd.method.map_or("not found", |method| method.name());
+    print.push_str(format!("  WKD: {method}\n").as_str());
+    for request in &wkd.requests {
+        print.push_str(format!("    {request}\n").as_str());
+    }
+    match &wkd.policy {
+        Some(policy) if !policy.trim().is_empty() => {
+            print.push_str("  WKD policy:\n");
+            for line in policy.lines() {
+                print.push_str(format!("    {line}\n").as_str());
+            }
+        }
+        Some(_) => print.push_str("  WKD policy: empty\n"),
+        None => print.push_str("  WKD policy: none\n"),
+    }
+    for warning in &wkd.warnings {
+        print.push_str(format!("  ⚠️ {warning}\n").as_str());
+    }
+    print
+}
+
+pub(crate) fn cert_email_addresses(cert: &Cert) -> Vec<String> {
+    cert.userids()
+        .filter_map(|user_id| {
+            user_id
+                .userid()
+                .email2()
+                .unwrap_or(None)
+                .map(str::to_string)
+        })
+        .collect()
+}
+
+/// Fetches an ASCII-Armored Public Key served over https
+pub async fn fetch_https(url: &str, retry_policy: &RetryPolicy) -> Result<Vec<Cert>> {
+    let client = reqwest::Client::new();
+    let armored_key = retry_policy
+        .send(|| client.get(url))
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(AppError::FailedToFetchKey)?
+        .text()
+        .await
+        .map_err(AppError::FailedToFetchKey)?;
+    Ok(vec![read_key_from_string(&armored_key)?])
+}
+
+/// Reads every ASCII-Armored or binary key from `key_path`, or from stdin when it is `-`
+pub fn read_certs_from_file(key_path: String) -> Result<Vec<Cert>> {
+    let key_bytes = read_key_bytes(&key_path)?;
+    read_certs_from_bytes(&key_bytes, &key_path)
+}
+
+/// Reads every ASCII-Armored or binary key from `key_bytes`, `source` names where they came from
+pub fn read_certs_from_bytes(key_bytes: &[u8], source: &str) -> Result<Vec<Cert>> {
+    let certs = CertParser::from_bytes(key_bytes)
+        .and_then(|cert_parser| cert_parser.collect::<sequoia_openpgp::Result<Vec<Cert>>>())
+        .map_err(|error| AppError::FailedToParseKey {
+            message: error.to_string(),
+        })?;
+
+    match certs.is_empty() {
+        true => Err(AppError::FailedToParseKey {
+            message: format!("no key found in {source}"),
+        }
+        .into()),
+        false => Ok(certs),
+    }
+}
+
+fn read_key_bytes(key_path: &str) -> Result<Vec<u8>, AppError> {
+    match key_path {
+        "-" => {
+            let mut key_bytes = Vec::new();
+            io::stdin().read_to_end(&mut key_bytes)?;
+            Ok(key_bytes)
+        }
+        _ => Ok(fs::read(key_path)?),
+    }
+}
+
+pub fn armor_cert(cert: &Cert) -> Result<String> {
+    let armored = cert
+        .armored()
+        .to_vec()
+        .map_err(|error| AppError::FailedToArmorKey {
+            message: error.to_string(),
+        })?;
+    Ok(String::from_utf8_lossy(&armored).into_owned())
+}
+
+pub fn user_id_to_user_id_string(user_id: UserID) -> String {
     let user_id_name = user_id.name2().unwrap_or(None).unwrap_or("");
     let user_id_email = user_id.email2().unwrap_or(None).unwrap_or("");
     format!("{user_id_name} <{user_id_email}>")
@@ -144,52 +675,194 @@ fn user_id_to_user_id_string(user_id: UserID) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::libs::context::cassette_context;
+    use crate::libs::doip::ProofStatus;
+    use doip_openpgp::openpgp::get_keys_doip_proofs;
+    use sequoia_openpgp::{
+        Packet, cert::CertBuilder, packet::signature::SignatureBuilder, types::SignatureType,
+    };
+    use std::time::Duration;
+
+    /// Proof results can only be recorded from the live services and none are in the cassettes,
+    /// so replaying leaves each of the `count` proofs of `key_profile` unverifiable offline
+    fn assert_replayed_proofs(key_profile: &KeyProfile, count: usize) {
+        let statuses: Vec<ProofStatus> = key_profile
+            .userid_proofs
+            .iter()
+            .flat_map(|userid_proofs| userid_proofs.proofs.iter().map(|proof| proof.status))
+            .collect();
+        assert_eq!(statuses.len(), count);
+        if cassette_context().cache.is_offline() {
+            assert_eq!(statuses, vec![ProofStatus::UnverifiableOffline; count]);
+        }
+    }
 
     #[tokio::test]
     async fn openpgp_wkd() {
-        let key_profiles = KeyProfile::new_from_wkd("wkd:alexis.lowe@chimbosonic.com".to_string())
-            .await
-            .unwrap();
+        let key_profiles =
+            KeyProfile::new_from_wkd("alexis.lowe@chimbosonic.com", &cassette_context())
+                .await
+                .unwrap();
         key_profiles[0].print(&PrintFormat::Text);
+        assert_replayed_proofs(&key_profiles[0], 12);
     }
 
     #[tokio::test]
     async fn openpgp_hkp_fingerprint() {
         let key_profiles = KeyProfile::new_from_hkp(
-            "hkp:3637202523E7C1309AB79E99EF2DC5827B445F4B".to_string(),
-            None,
+            "3637202523E7C1309AB79E99EF2DC5827B445F4B",
+            &Keyservers::default(),
+            &cassette_context(),
         )
         .await
         .unwrap();
         key_profiles[0].print(&PrintFormat::Text);
+        assert_replayed_proofs(&key_profiles[0], 1);
     }
 
     #[tokio::test]
     async fn openpgp_hkp_email() {
-        let key_profiles = KeyProfile::new_from_hkp("hkp:test@doip.rocks".to_string(), None)
-            .await
-            .unwrap();
+        let key_profiles = KeyProfile::new_from_hkp(
+            "test@doip.rocks",
+            &Keyservers::default(),
+            &cassette_context(),
+        )
+        .await
+        .unwrap();
         key_profiles[0].print(&PrintFormat::Text);
+        assert_replayed_proofs(&key_profiles[0], 1);
     }
 
     #[tokio::test]
     async fn openpgp_hkps() {
-        let key_profiles = KeyProfile::new_from_hkp(
-            "hkps:3637202523E7C1309AB79E99EF2DC5827B445F4B".to_string(),
-            None,
+        let profile_uri: ProfileUri = "hkps:3637202523E7C1309AB79E99EF2DC5827B445F4B"
+            .parse()
+            .unwrap();
+        let key_profiles =
+            KeyProfile::new_from_uri(&profile_uri, &Keyservers::default(), &cassette_context())
+                .await
+                .unwrap();
+        key_profiles[0].print(&PrintFormat::Text);
+        assert_replayed_proofs(&key_profiles[0], 1);
+    }
+
+    #[tokio::test]
+    async fn openpgp_openpgp4fpr() {
+        let profile_uri: ProfileUri = "openpgp4fpr:3637202523E7C1309AB79E99EF2DC5827B445F4B"
+            .parse()
+            .unwrap();
+        let key_profiles =
+            KeyProfile::new_from_uri(&profile_uri, &Keyservers::default(), &cassette_context())
+                .await
+                .unwrap();
+        key_profiles[0].print(&PrintFormat::Text);
+        assert_replayed_proofs(&key_profiles[0], 1);
+    }
+
+    #[tokio::test]
+    async fn openpgp_from_file() {
+        let key_profiles = KeyProfile::new_from_file(
+            "__tests__/data/IETF_SAMPLE_PUBLIC_KEY_WITH_NOTATIONS.asc".to_string(),
+            &cassette_context(),
         )
         .await
         .unwrap();
         key_profiles[0].print(&PrintFormat::Text);
+        assert_replayed_proofs(&key_profiles[0], 1);
     }
 
     #[tokio::test]
-    async fn openpgp_from_file() {
+    async fn openpgp_from_binary_file() {
         let key_profiles = KeyProfile::new_from_file(
-            "__tests__/data/IETF_SAMPLE_PUBLIC_KEY_WITH_NOTATIONS.asc".to_string(),
+            "__tests__/data/TEST_DOIP_PUBLIC_KEY.gpg".to_string(),
+            &cassette_context(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            key_profiles[0].fingerprint,
+            "3637202523E7C1309AB79E99EF2DC5827B445F4B"
+        );
+    }
+
+    #[tokio::test]
+    async fn openpgp_from_keyring_file() {
+        let key_profiles = KeyProfile::new_from_file(
+            "__tests__/data/KEYRING_PUBLIC_KEYS.asc".to_string(),
+            &cassette_context(),
         )
         .await
         .unwrap();
-        key_profiles.print(&PrintFormat::Text);
+        let fingerprints: Vec<&str> = key_profiles
+            .iter()
+            .map(|key_profile| key_profile.fingerprint.as_str())
+            .collect();
+        assert_eq!(
+            fingerprints,
+            [
+                "3637202523E7C1309AB79E99EF2DC5827B445F4B",
+                "AC48BC1F029B6188D97E2D807C855DB4466DF0C6"
+            ]
+        );
+    }
+
+    #[test]
+    fn doip_proofs_at_reference_time() {
+        let cert = read_certs_from_file("__tests__/data/TEST_DOIP_PUBLIC_KEY.asc".to_string())
+            .unwrap()
+            .remove(0);
+        #[allow(clippy::mutable_key_type)]
+        let current_proofs = get_keys_doip_proofs(&cert).unwrap();
+        let proofs_now = doip_proofs(&cert, None);
+        assert_eq!(proofs_now.len(), current_proofs.len());
+        for (user_id, proofs) in proofs_now {
+            assert_eq!(proofs.len(), current_proofs[&user_id].len());
+            for proof in proofs {
+                assert!(current_proofs[&user_id].contains(&proof.uri));
+                assert!(proof.signature.current);
+                assert!(proof.signature.created.is_some());
+            }
+        }
+
+        let before_creation = cert.primary_key().key().creation_time() - Duration::from_secs(1);
+        assert!(doip_proofs(&cert, Some(before_creation)).is_empty());
+    }
+
+    #[tokio::test]
+    async fn proofs_of_fallback_signatures_are_not_verified() {
+        let created = SystemTime::now() - Duration::from_secs(2 * 24 * 60 * 60);
+        let (cert, _) = CertBuilder::new()
+            .set_creation_time(created)
+            .generate()
+            .unwrap();
+        let mut signer = cert
+            .primary_key()
+            .key()
+            .clone()
+            .parts_into_secret()
+            .unwrap()
+            .into_keypair()
+            .unwrap();
+        let userid = UserID::from("test@doip.rocks");
+        let expired_binding = SignatureBuilder::new(SignatureType::PositiveCertification)
+            .set_signature_creation_time(created)
+            .unwrap()
+            .set_signature_validity_period(Duration::from_secs(60 * 60))
+            .unwrap()
+            .add_notation("proof@ariadne.id", b"dns:doip.rocks?type=TXT", None, false)
+            .unwrap()
+            .sign_userid_binding(&mut signer, None, &userid)
+            .unwrap();
+        let cert = cert
+            .insert_packets(vec![Packet::from(userid), Packet::from(expired_binding)])
+            .unwrap();
+
+        let key_profile = KeyProfile::new(cert, &VerifyContext::default())
+            .await
+            .unwrap();
+        let proofs = &key_profile.userid_proofs[0].proofs;
+        assert_eq!(proofs.len(), 1);
+        assert_eq!(proofs[0].status, ProofStatus::InvalidKey);
+        assert!(!proofs[0].signature.as_ref().unwrap().current);
     }
 }
@@ -0,0 +1,170 @@
+use std::{fmt, process::ExitCode, str::FromStr};
+
+use super::{doip::VerifiedProof, error::AppError};
+
+/// How many proofs must be verified for a run to be considered successful
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum RequirePolicy {
+    /// Every proof must be verified, and there must be at least one
+    #[default]
+    All,
+    /// At least this many proofs must be verified
+    AtLeast(usize),
+}
+
+impl FromStr for RequirePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(RequirePolicy::All),
+            n => n
+                .parse()
+                .map(RequirePolicy::AtLeast)
+                .map_err(|_| format!("expected a number or `all`, got `{n}`")),
+        }
+    }
+}
+
+impl fmt::Display for RequirePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequirePolicy::All => write!(f, "all"),
+            RequirePolicy::AtLeast(n) => write!(f, "{n}"),
+        }
+    }
+}
+
+/// Process exit codes, see the README for the documented values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// The `--require` policy is satisfied
+    Verified = 0,
+    /// Some proofs were verified but not enough to satisfy `--require`
+    SomeFailed = 1,
+    /// The input could not be parsed, shares its code with clap's usage errors
+    MalformedInput = 2,
+    /// No proof was verified
+    NoneVerified = 3,
+    /// The profile could not be fetched or processed
+    FetchFailed = 4,
+}
+
+impl ExitStatus {
+    /// Evaluates `policy` against the result of every proof of a run
+    pub fn evaluate<'a>(
+        proofs: impl IntoIterator<Item = &'a VerifiedProof>,
+        policy: &RequirePolicy,
+    ) -> Self {
+        let (total, verified) = proofs.into_iter().fold((0, 0), |(total, verified), proof| {
+            (total + 1, verified + usize::from(proof.is_verified()))
+        });
+
+        let satisfied = match policy {
+            RequirePolicy::All => total > 0 && verified == total,
+            RequirePolicy::AtLeast(n) => verified >= *n,
+        };
+
+        match (satisfied, verified) {
+            (true, _) => ExitStatus::Verified,
+            (false, 0) => ExitStatus::NoneVerified,
+            (false, _) => ExitStatus::SomeFailed,
+        }
+    }
+
+    pub fn from_error(report: &miette::Report) -> Self {
+        match report.downcast_ref::<AppError>() {
+            Some(
+                AppError::ProfileURIMalformed { .. }
+                | AppError::InvalidEmailAddress { .. }
+                | AppError::InvalidFingerprint { .. }
+                | AppError::InvalidAspeUri { .. }
+                | AppError::FailedToParseAspeUri
+                | AppError::FailedToParseKey { .. }
+                | AppError::ProfileNotProvided
+                | AppError::InvalidConfig { .. }
+                | AppError::InvalidKeyserver { .. }
+                | AppError::LegacyArgsWithSubcommand,
+            ) => ExitStatus::MalformedInput,
+            _ => ExitStatus::FetchFailed,
+        }
+    }
+}
+
+impl From<ExitStatus> for ExitCode {
+    fn from(exit_status: ExitStatus) -> Self {
+        ExitCode::from(exit_status as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::doip::{AppVerificationResult, ProofStatus};
+
+    fn proofs(results: &[bool]) -> Vec<VerifiedProof> {
+        results
+            .iter()
+            .map(|result| VerifiedProof {
+                uri: "dns:doip.rocks".to_string(),
+                status: match result {
+                    true => ProofStatus::Verified,
+                    false => ProofStatus::NotVerified,
+                },
+                verification_result: Some(AppVerificationResult {
+                    result: *result,
+                    service_provider_info: None,
+                    proxy_used: None,
+                }),
+                error: None,
+                attempts: 1,
+                signature: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn require_policy_from_str() {
+        assert_eq!("all".parse(), Ok(RequirePolicy::All));
+        assert_eq!("3".parse(), Ok(RequirePolicy::AtLeast(3)));
+        assert!("some".parse::<RequirePolicy>().is_err());
+    }
+
+    #[test]
+    fn evaluate_require_all() {
+        let policy = RequirePolicy::All;
+        assert_eq!(
+            ExitStatus::evaluate(&proofs(&[true, true]), &policy),
+            ExitStatus::Verified
+        );
+        assert_eq!(
+            ExitStatus::evaluate(&proofs(&[true, false]), &policy),
+            ExitStatus::SomeFailed
+        );
+        assert_eq!(
+            ExitStatus::evaluate(&proofs(&[false, false]), &policy),
+            ExitStatus::NoneVerified
+        );
+        assert_eq!(
+            ExitStatus::evaluate(&proofs(&[]), &policy),
+            ExitStatus::NoneVerified
+        );
+    }
+
+    #[test]
+    fn evaluate_require_at_least() {
+        let policy = RequirePolicy::AtLeast(1);
+        assert_eq!(
+            ExitStatus::evaluate(&proofs(&[true, false]), &policy),
+            ExitStatus::Verified
+        );
+        assert_eq!(
+            ExitStatus::evaluate(&proofs(&[false]), &policy),
+            ExitStatus::NoneVerified
+        );
+        assert_eq!(
+            ExitStatus::evaluate(&proofs(&[true, false]), &RequirePolicy::AtLeast(2)),
+            ExitStatus::SomeFailed
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use std::sync::Arc;
+
+use super::{
+    aspe::AspProfile,
+    context::VerifyContext,
+    discover::EmailProfile,
+    doip::{PrintFormat, Printable, ProofStatus, VerifiedProof},
+    keyserver::Keyservers,
+    openpgp::KeyProfile,
+    uri::ProfileUri,
+};
+use display_json::{DebugAsJsonPretty, DisplayAsJson};
+use miette::Result;
+use serde::Serialize;
+
+/// A verified profile as returned by [`verify`]
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum Profile {
+    /// Every key found for an OpenPGP profile uri, a `hkp:` lookup or keyring file can return several
+    Keys(Vec<KeyProfile>),
+    /// An Ariadne Signature Profile
+    Asp(AspProfile),
+    /// Every key discovered from an email address
+    Email(EmailProfile),
+}
+
+impl Printable for Profile {
+    /// JSON output is a single document, a key, an array of keys, an ASP or an email profile.
+    /// The summary of several profiles is only printed as text as it can be derived from them
+    fn print(&self, print_format: &PrintFormat) {
+        match (self, print_format) {
+            (Profile::Keys(key_profiles), PrintFormat::Json) if key_profiles.len() != 1 => {
+                println!(
+                    "{}",
+                    serde_json::to_string(key_profiles).unwrap_or_default()
+                )
+            }
+            (Profile::Keys(key_profiles), PrintFormat::JsonPretty) if key_profiles.len() != 1 => {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(key_profiles).unwrap_or_default()
+                )
+            }
+            (Profile::Keys(key_profiles), _) => {
+                for key_profile in key_profiles {
+                    key_profile.print(print_format);
+                }
+                if key_profiles.len() > 1 {
+                    Summary::new(self).print(print_format);
+                }
+            }
+            (Profile::Asp(asp_profile), _) => asp_profile.print(print_format),
+            (Profile::Email(email_profile), _) => {
+                email_profile.print(print_format);
+                if email_profile.profile_count() > 1 {
+                    Summary::new(self).print(print_format);
+                }
+            }
+        }
+    }
+}
+
+/// Proof counts aggregated over every key of a profile
+#[derive(Serialize, DisplayAsJson, DebugAsJsonPretty, Default)]
+pub struct Summary {
+    pub keys: usize,
+    pub proofs: usize,
+    pub verified: usize,
+    pub not_verified: usize,
+    pub error: usize,
+    pub no_matching_provider: usize,
+    pub timed_out: usize,
+    pub unverifiable_offline: usize,
+    pub invalid_key: usize,
+}
+
+impl Summary {
+    pub fn new(profile: &Profile) -> Self {
+        let mut summary = Summary {
+            keys: match profile {
+                Profile::Keys(key_profiles) => key_profiles.len(),
+                Profile::Asp(_) => 1,
+                Profile::Email(email_profile) => email_profile.profile_count(),
+            },
+            ..Default::default()
+        };
+
+        for proof in profile.proofs() {
+            summary.proofs += 1;
+            match proof.status {
+                ProofStatus::Verified => summary.verified += 1,
+                ProofStatus::NotVerified => summary.not_verified += 1,
+                ProofStatus::Error => summary.error += 1,
+                ProofStatus::NoMatchingProvider => summary.no_matching_provider += 1,
+                ProofStatus::TimedOut => summary.timed_out += 1,
+                ProofStatus::UnverifiableOffline => summary.unverifiable_offline += 1,
+                ProofStatus::InvalidKey => summary.invalid_key += 1,
+            }
+        }
+
+        summary
+    }
+}
+
+impl Printable for Summary {
+    /// Only printed as text, JSON output holds the proofs the summary is made of
+    fn print(&self, print_format: &PrintFormat) {
+        match print_format {
+            PrintFormat::Json | PrintFormat::JsonPretty | PrintFormat::Ndjson => {}
+            PrintFormat::Text => {
+                println!(
+                    "Summary: {} keys, {}/{} proofs verified ({} {}, {} {}, {} {}, {} {}, {} {}, {} {})",
+                    self.keys,
+                    self.verified,
+                    self.proofs,
+                    self.not_verified,
+                    ProofStatus::NotVerified.symbol(),
+                    self.error,
+                    ProofStatus::Error.symbol(),
+                    self.no_matching_provider,
+                    ProofStatus::NoMatchingProvider.symbol(),
+                    self.timed_out,
+                    ProofStatus::TimedOut.symbol(),
+                    self.unverifiable_offline,
+                    ProofStatus::UnverifiableOffline.symbol(),
+                    self.invalid_key,
+                    ProofStatus::InvalidKey.symbol(),
+                );
+            }
+        }
+    }
+}
+
+impl Profile {
+    /// Every proof of the profile, across all of its keys
+    pub fn proofs(&self) -> Vec<&VerifiedProof> {
+        match self {
+            Profile::Keys(key_profiles) => {
+                key_profiles.iter().flat_map(KeyProfile::proofs).collect()
+            }
+            Profile::Asp(asp_profile) => asp_profile.proofs().collect(),
+            Profile::Email(email_profile) => email_profile.proofs().collect(),
+        }
+    }
+}
+
+/// Options used by [`verify_with_options`]
+#[derive(Debug, Default, Clone)]
+pub struct VerifyOptions {
+    /// Keyservers used for hkp lookup, defaults to keys.openpgp.org
+    pub keyservers: Keyservers,
+    /// Skip SSL Verification for Aspe Profile Fetch
+    pub skip_verify_ssl: bool,
+    /// Limits, retry policy, cache and key evaluation shared by every profile verified with
+    /// these options
+    pub context: Arc<VerifyContext>,
+}
+
+/// Fetches the profile behind `uri` and verifies all of its claims using the default options
+pub async fn verify(uri: &str) -> Result<Profile> {
+    verify_with_options(uri, &VerifyOptions::default()).await
+}
+
+/// Fetches the profile behind `uri` and verifies all of its claims
+pub async fn verify_with_options(uri: &str, options: &VerifyOptions) -> Result<Profile> {
+    let profile_uri: ProfileUri = uri.parse()?;
+    verify_profile_uri(&profile_uri, options).await
+}
+
+/// Fetches the profile behind an already parsed `profile_uri` and verifies all of its claims
+pub async fn verify_profile_uri(
+    profile_uri: &ProfileUri,
+    options: &VerifyOptions,
+) -> Result<Profile> {
+    match profile_uri {
+        ProfileUri::Aspe { .. } => Ok(Profile::Asp(
+            AspProfile::new(
+                &profile_uri.to_string(),
+                options.skip_verify_ssl,
+                &options.context,
+            )
+            .await?,
+        )),
+        ProfileUri::Email(email_address) => Ok(Profile::Email(
+            EmailProfile::new(email_address, &options.keyservers, &options.context).await?,
+        )),
+        _ => Ok(Profile::Keys(
+            KeyProfile::new_from_uri(profile_uri, &options.keyservers, &options.context).await?,
+        )),
+    }
+}
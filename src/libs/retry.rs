@@ -0,0 +1,233 @@
+use std::{error::Error, future::Future, io, time::Duration};
+
+use reqwest::{RequestBuilder, Response, StatusCode, header::RETRY_AFTER};
+use tokio::time::sleep;
+
+use super::doip::request_error;
+
+pub const DEFAULT_RETRIES: u32 = 2;
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(10);
+
+/// How often and how patiently transient failures are retried
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Number of attempts made after the first one, `0` disables retrying
+    pub retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(retries: u32) -> Self {
+        RetryPolicy {
+            retries,
+            base_delay: BASE_DELAY,
+            max_delay: MAX_DELAY,
+        }
+    }
+
+    /// Delay before retrying after `attempt` failed, doubling every attempt with up to half
+    /// of it taken off at random so concurrent retries don't hit a host at the same time
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(1_u32 << attempt.saturating_sub(1).min(16))
+            .min(self.max_delay);
+        delay / 2 + delay.mul_f64(rand::random::<f64>() / 2.0)
+    }
+
+    /// Runs `operation` until it succeeds, fails with an error that isn't transient or runs
+    /// out of retries. Errors only carry the failed request and not its response, so unlike
+    /// [`RetryPolicy::send`] this can't wait for `Retry-After`
+    pub async fn run<T, E, F, Fut>(&self, operation: F) -> Result<T, E>
+    where
+        E: Error + 'static,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        self.run_counting(&mut 0, operation).await
+    }
+
+    /// Same as [`RetryPolicy::run`], counting every attempt made in `attempts` so the count
+    /// survives the future being cancelled
+    pub async fn run_counting<T, E, F, Fut>(
+        &self,
+        attempts: &mut u32,
+        mut operation: F,
+    ) -> Result<T, E>
+    where
+        E: Error + 'static,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        loop {
+            *attempts += 1;
+            match operation().await {
+                Err(error) if *attempts <= self.retries && is_transient(&error) => {
+                    sleep(self.backoff(*attempts)).await
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Sends the request built by `request` until the server answers with something other
+    /// than a 429 or 5xx, waiting for `Retry-After` when the server sends one, up to `max_delay`
+    pub async fn send(&self, request: impl Fn() -> RequestBuilder) -> reqwest::Result<Response> {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let can_retry = attempts <= self.retries;
+            match request().send().await {
+                Ok(response) if can_retry && is_transient_status(response.status()) => {
+                    let delay = match retry_after(&response) {
+                        Some(retry_after) => retry_after.min(self.max_delay),
+                        None => self.backoff(attempts),
+                    };
+                    sleep(delay).await
+                }
+                Err(error) if can_retry && is_transient(&error) => {
+                    sleep(self.backoff(attempts)).await
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(DEFAULT_RETRIES)
+    }
+}
+
+/// Whether `error` may go away on a later attempt: the request behind it timed out, its
+/// connection was refused or reset or it got a 429 or 5xx. Names that don't resolve won't
+/// resolve a moment later, so DNS failures aren't retried
+pub fn is_transient(error: &(dyn Error + 'static)) -> bool {
+    request_error(error).is_some_and(|error| {
+        error.is_timeout()
+            || error.status().is_some_and(is_transient_status)
+            || io_error_kind(error).is_some_and(|kind| {
+                matches!(
+                    kind,
+                    io::ErrorKind::ConnectionRefused
+                        | io::ErrorKind::ConnectionReset
+                        | io::ErrorKind::ConnectionAborted
+                )
+            })
+    })
+}
+
+/// Kind of the IO error somewhere in the source chain of `error`
+fn io_error_kind(error: &(dyn Error + 'static)) -> Option<io::ErrorKind> {
+    let mut source = error.source();
+    while let Some(error) = source {
+        if let Some(io_error) = error.downcast_ref::<io::Error>() {
+            return Some(io_error.kind());
+        }
+        source = error.source();
+    }
+    None
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// `Retry-After` given in seconds, HTTP dates fall back to the usual backoff
+fn retry_after(response: &Response) -> Option<Duration> {
+    let seconds = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+    Some(Duration::from_secs(seconds.trim().parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libs::doip::tests::{refused_url, serve};
+
+    #[test]
+    fn backoff_doubles_up_to_max_delay() {
+        let retry_policy = RetryPolicy::new(5);
+        for (attempt, delay) in [(1, 500), (2, 1000), (3, 2000), (10, 10_000)] {
+            let backoff = retry_policy.backoff(attempt);
+            assert!(backoff >= Duration::from_millis(delay / 2));
+            assert!(backoff <= Duration::from_millis(delay));
+        }
+    }
+
+    #[tokio::test]
+    async fn transient_errors() {
+        for (response, transient) in [
+            (
+                "HTTP/1.1 503 Service Unavailable\r\nconnection: close\r\ncontent-length: 0\r\n\r\n",
+                true,
+            ),
+            (
+                "HTTP/1.1 429 Too Many Requests\r\nconnection: close\r\ncontent-length: 0\r\n\r\n",
+                true,
+            ),
+            (
+                "HTTP/1.1 404 Not Found\r\nconnection: close\r\ncontent-length: 0\r\n\r\n",
+                false,
+            ),
+        ] {
+            let error = reqwest::get(serve(vec![response]))
+                .await
+                .unwrap()
+                .error_for_status()
+                .unwrap_err();
+            assert_eq!(is_transient(&error), transient, "{response}");
+        }
+
+        let error = reqwest::get(refused_url()).await.unwrap_err();
+        assert!(is_transient(&error));
+        // `.invalid` never resolves
+        let error = reqwest::get("http://keyoxide.invalid/").await.unwrap_err();
+        assert!(error.is_connect());
+        assert!(!is_transient(&error));
+        assert!(!is_transient(&io::Error::other("not a request")));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn send_waits_for_retry_after_up_to_max_delay() {
+        let url = serve(vec![
+            "HTTP/1.1 503 Service Unavailable\r\nretry-after: 3600\r\nconnection: close\r\ncontent-length: 0\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nconnection: close\r\ncontent-length: 0\r\n\r\n",
+        ]);
+        let retry_policy = RetryPolicy::new(1);
+        let client = reqwest::Client::new();
+        let start = tokio::time::Instant::now();
+        let response = retry_policy.send(|| client.get(&url)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(start.elapsed() >= retry_policy.max_delay);
+        assert!(start.elapsed() < Duration::from_secs(3600));
+    }
+
+    #[tokio::test]
+    async fn run_counts_attempts() {
+        let retry_policy = RetryPolicy {
+            retries: 2,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+        };
+        let url = refused_url();
+
+        let mut attempts = 0;
+        let result = retry_policy
+            .run_counting(&mut attempts, || reqwest::get(&url))
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+
+        let mut attempts = 0;
+        let result: Result<(), io::Error> = retry_policy
+            .run_counting(&mut attempts, || async {
+                Err(io::Error::other("not found"))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+}
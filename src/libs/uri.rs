@@ -0,0 +1,447 @@
+use std::{fmt, str::FromStr};
+
+use super::error::AppError;
+
+/// A validated uri pointing at a profile
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProfileUri {
+    /// `hkp:<email_address>`, `hkp:<key_fingerprint>` or `hkp:<key_id>`, fingerprints and key
+    /// ids may be prefixed with `0x`
+    Hkp(String),
+    /// `hkps:<email_address>`, `hkps:<key_fingerprint>` or `hkps:<key_id>`
+    Hkps(String),
+    /// `wkd:<email_address>`
+    Wkd(String),
+    /// `email:<email_address>` or a bare email address, discovered over WKD and keyservers
+    Email(String),
+    /// `aspe:<domain>:<fingerprint>`
+    Aspe { domain: String, fingerprint: String },
+    /// `openpgp4fpr:<key_fingerprint>`, optionally followed by `#a=<email_address>` as in
+    /// OpenPGP QR codes, the address is used to look the key up over WKD
+    Openpgp4fpr {
+        fingerprint: String,
+        email_address: Option<String>,
+    },
+    /// `file:<path>`, `file:-` reads from stdin
+    File(String),
+    /// `https://<url>` of an ASCII-Armored Public Key
+    Https(String),
+}
+
+impl ProfileUri {
+    /// Identifier passed to the keyserver for `hkp:` and `hkps:` uris
+    pub fn hkp_identifier(&self) -> Option<&str> {
+        match self {
+            ProfileUri::Hkp(identifier) | ProfileUri::Hkps(identifier) => Some(identifier),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ProfileUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileUri::Hkp(identifier) => write!(f, "hkp:{identifier}"),
+            ProfileUri::Hkps(identifier) => write!(f, "hkps:{identifier}"),
+            ProfileUri::Wkd(email_address) => write!(f, "wkd:{email_address}"),
+            ProfileUri::Email(email_address) => write!(f, "email:{email_address}"),
+            ProfileUri::Aspe {
+                domain,
+                fingerprint,
+            } => write!(f, "aspe:{domain}:{fingerprint}"),
+            ProfileUri::Openpgp4fpr {
+                fingerprint,
+                email_address: None,
+            } => write!(f, "openpgp4fpr:{fingerprint}"),
+            ProfileUri::Openpgp4fpr {
+                fingerprint,
+                email_address: Some(email_address),
+            } => write!(
+                f,
+                "openpgp4fpr:{fingerprint}#{}",
+                fragment_query("a", email_address)
+            ),
+            ProfileUri::File(path) => write!(f, "file:{path}"),
+            ProfileUri::Https(url) => write!(f, "{url}"),
+        }
+    }
+}
+
+impl FromStr for ProfileUri {
+    type Err = AppError;
+
+    fn from_str(uri: &str) -> Result<Self, Self::Err> {
+        let Some((scheme, rest)) = uri.split_once(':') else {
+            if is_valid_email_address(uri) {
+                return Ok(ProfileUri::Email(uri.to_string()));
+            }
+            return Err(AppError::ProfileURIMalformed {
+                uri: uri.to_string(),
+                span: (0, uri.len()).into(),
+            });
+        };
+        // Offset of `rest` in `uri`, used to point diagnostics at the offending part
+        let offset = scheme.len() + 1;
+
+        match scheme.to_ascii_lowercase().as_str() {
+            "hkp" => Ok(ProfileUri::Hkp(validate_hkp_identifier(uri, rest, offset)?)),
+            "hkps" => Ok(ProfileUri::Hkps(validate_hkp_identifier(
+                uri, rest, offset,
+            )?)),
+            "wkd" => Ok(ProfileUri::Wkd(validate_email_address(uri, rest, offset)?)),
+            "email" => Ok(ProfileUri::Email(validate_email_address(
+                uri, rest, offset,
+            )?)),
+            "openpgp4fpr" => {
+                let (fingerprint, fragment) = match rest.split_once('#') {
+                    Some((fingerprint, fragment)) => (fingerprint, Some(fragment)),
+                    None => (rest, None),
+                };
+                let fragment_offset = offset + fingerprint.len() + 1;
+                let email_address = match fragment.map(fragment_email_address) {
+                    Some(Some((value_offset, raw_email_address, email_address))) => {
+                        match is_valid_email_address(&email_address) {
+                            true => Some(email_address),
+                            false => {
+                                return Err(AppError::InvalidEmailAddress {
+                                    uri: uri.to_string(),
+                                    span: (fragment_offset + value_offset, raw_email_address.len())
+                                        .into(),
+                                });
+                            }
+                        }
+                    }
+                    Some(None) => {
+                        return Err(AppError::InvalidEmailAddress {
+                            uri: uri.to_string(),
+                            span: (fragment_offset, uri.len() - fragment_offset).into(),
+                        });
+                    }
+                    None => None,
+                };
+                Ok(ProfileUri::Openpgp4fpr {
+                    fingerprint: validate_fingerprint(uri, fingerprint, offset)?,
+                    email_address,
+                })
+            }
+            "aspe" => {
+                let Some((domain, fingerprint)) = rest.split_once(':') else {
+                    return Err(AppError::InvalidAspeUri {
+                        uri: uri.to_string(),
+                        span: (offset, rest.len()).into(),
+                    });
+                };
+                if !is_valid_domain(domain) {
+                    return Err(AppError::InvalidAspeUri {
+                        uri: uri.to_string(),
+                        span: (offset, domain.len()).into(),
+                    });
+                }
+                if !is_valid_aspe_fingerprint(fingerprint) {
+                    return Err(AppError::InvalidAspeUri {
+                        uri: uri.to_string(),
+                        span: (offset + domain.len() + 1, fingerprint.len()).into(),
+                    });
+                }
+                Ok(ProfileUri::Aspe {
+                    domain: domain.to_string(),
+                    fingerprint: fingerprint.to_string(),
+                })
+            }
+            "file" if !rest.is_empty() => Ok(ProfileUri::File(rest.to_string())),
+            "https" if reqwest::Url::parse(uri).is_ok_and(|url| url.has_host()) => {
+                Ok(ProfileUri::Https(uri.to_string()))
+            }
+            "file" | "https" => Err(AppError::ProfileURIMalformed {
+                uri: uri.to_string(),
+                span: (offset, rest.len()).into(),
+            }),
+            _ => Err(AppError::ProfileURIMalformed {
+                uri: uri.to_string(),
+                span: (0, scheme.len()).into(),
+            }),
+        }
+    }
+}
+
+fn validate_hkp_identifier(uri: &str, identifier: &str, offset: usize) -> Result<String, AppError> {
+    if identifier.contains('@') {
+        validate_email_address(uri, identifier, offset)
+    } else {
+        validate_key_handle(uri, identifier, offset)
+    }
+}
+
+/// Fingerprints and 16 hex character key ids, optionally prefixed with `0x` which is kept as
+/// keyservers accept it
+fn validate_key_handle(uri: &str, key_handle: &str, offset: usize) -> Result<String, AppError> {
+    let (prefix, hex) = match key_handle.get(..2) {
+        Some("0x" | "0X") => key_handle.split_at(2),
+        _ => ("", key_handle),
+    };
+    match is_valid_fingerprint(hex) || is_valid_key_id(hex) {
+        true => Ok(format!(
+            "{}{}",
+            prefix.to_ascii_lowercase(),
+            hex.to_ascii_uppercase()
+        )),
+        false => Err(AppError::InvalidFingerprint {
+            uri: uri.to_string(),
+            span: (offset, key_handle.len()).into(),
+        }),
+    }
+}
+
+fn validate_email_address(
+    uri: &str,
+    email_address: &str,
+    offset: usize,
+) -> Result<String, AppError> {
+    match is_valid_email_address(email_address) {
+        true => Ok(email_address.to_string()),
+        false => Err(AppError::InvalidEmailAddress {
+            uri: uri.to_string(),
+            span: (offset, email_address.len()).into(),
+        }),
+    }
+}
+
+/// The `a` parameter of an openpgp4fpr fragment, e.g. `a=alice%40example.org&n=Alice`, along
+/// with its offset in the fragment and its raw value. Only percent-encoding is decoded, a `+`
+/// is part of the address and not an encoded space
+fn fragment_email_address(fragment: &str) -> Option<(usize, &str, String)> {
+    let mut param_offset = 0;
+    for param in fragment.split('&') {
+        if let Some(raw_email_address) = param.strip_prefix("a=") {
+            let mut url = reqwest::Url::parse("openpgp4fpr:").ok()?;
+            url.set_query(Some(&format!(
+                "a={}",
+                raw_email_address.replace('+', "%2B")
+            )));
+            let (_, email_address) = url.query_pairs().next()?;
+            return Some((
+                param_offset + 2,
+                raw_email_address,
+                email_address.into_owned(),
+            ));
+        }
+        param_offset += param.len() + 1;
+    }
+    None
+}
+
+/// `<name>=<value>` with the value percent-encoded, as in openpgp4fpr fragments
+fn fragment_query(name: &str, value: &str) -> String {
+    let mut url = reqwest::Url::parse("openpgp4fpr:").expect("openpgp4fpr: is a valid url");
+    url.query_pairs_mut().append_pair(name, value);
+    url.query().unwrap_or_default().to_string()
+}
+
+fn validate_fingerprint(uri: &str, fingerprint: &str, offset: usize) -> Result<String, AppError> {
+    match is_valid_fingerprint(fingerprint) {
+        true => Ok(fingerprint.to_ascii_uppercase()),
+        false => Err(AppError::InvalidFingerprint {
+            uri: uri.to_string(),
+            span: (offset, fingerprint.len()).into(),
+        }),
+    }
+}
+
+fn is_valid_email_address(email_address: &str) -> bool {
+    match email_address.split_once('@') {
+        Some((local_part, domain)) => {
+            !local_part.is_empty()
+                && !local_part
+                    .chars()
+                    .any(|c| c.is_whitespace() || c.is_control() || c == '@')
+                && is_valid_domain(domain)
+                && domain.contains('.')
+        }
+        None => false,
+    }
+}
+
+fn is_valid_domain(domain: &str) -> bool {
+    !domain.is_empty()
+        && domain.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        })
+}
+
+/// OpenPGP v4 fingerprints are 40 hex characters, v6 fingerprints are 64
+fn is_valid_fingerprint(fingerprint: &str) -> bool {
+    matches!(fingerprint.len(), 40 | 64) && fingerprint.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// OpenPGP key ids are 16 hex characters
+fn is_valid_key_id(key_id: &str) -> bool {
+    key_id.len() == 16 && key_id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// ASPE fingerprints are 26 base32 characters
+fn is_valid_aspe_fingerprint(fingerprint: &str) -> bool {
+    fingerprint.len() == 26
+        && fingerprint
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || ('2'..='7').contains(&c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_valid_uris() {
+        assert_eq!(
+            "hkp:test@doip.rocks".parse::<ProfileUri>().unwrap(),
+            ProfileUri::Hkp("test@doip.rocks".to_string())
+        );
+        assert_eq!(
+            "hkps:3637202523e7c1309ab79e99ef2dc5827b445f4b"
+                .parse::<ProfileUri>()
+                .unwrap(),
+            ProfileUri::Hkps("3637202523E7C1309AB79E99EF2DC5827B445F4B".to_string())
+        );
+        assert_eq!(
+            "hkp:ef2dc5827b445f4b".parse::<ProfileUri>().unwrap(),
+            ProfileUri::Hkp("EF2DC5827B445F4B".to_string())
+        );
+        assert_eq!(
+            "hkp:0x3637202523e7c1309ab79e99ef2dc5827b445f4b"
+                .parse::<ProfileUri>()
+                .unwrap(),
+            ProfileUri::Hkp("0x3637202523E7C1309AB79E99EF2DC5827B445F4B".to_string())
+        );
+        assert_eq!(
+            "hkps:0XEF2DC5827B445F4B".parse::<ProfileUri>().unwrap(),
+            ProfileUri::Hkps("0xEF2DC5827B445F4B".to_string())
+        );
+        assert_eq!(
+            "wkd:alexis.lowe@chimbosonic.com"
+                .parse::<ProfileUri>()
+                .unwrap(),
+            ProfileUri::Wkd("alexis.lowe@chimbosonic.com".to_string())
+        );
+        assert_eq!(
+            "email:test@doip.rocks".parse::<ProfileUri>().unwrap(),
+            ProfileUri::Email("test@doip.rocks".to_string())
+        );
+        assert_eq!(
+            "test@doip.rocks".parse::<ProfileUri>().unwrap(),
+            ProfileUri::Email("test@doip.rocks".to_string())
+        );
+        assert_eq!(
+            "aspe:keyoxide.org:TOICV3SYXNJP7E4P5AOK5DHW44"
+                .parse::<ProfileUri>()
+                .unwrap(),
+            ProfileUri::Aspe {
+                domain: "keyoxide.org".to_string(),
+                fingerprint: "TOICV3SYXNJP7E4P5AOK5DHW44".to_string()
+            }
+        );
+        assert_eq!(
+            "openpgp4fpr:3637202523E7C1309AB79E99EF2DC5827B445F4B"
+                .parse::<ProfileUri>()
+                .unwrap(),
+            ProfileUri::Openpgp4fpr {
+                fingerprint: "3637202523E7C1309AB79E99EF2DC5827B445F4B".to_string(),
+                email_address: None,
+            }
+        );
+        assert_eq!(
+            "openpgp4fpr:3637202523E7C1309AB79E99EF2DC5827B445F4B#a=test%40doip.rocks&n=Test"
+                .parse::<ProfileUri>()
+                .unwrap(),
+            ProfileUri::Openpgp4fpr {
+                fingerprint: "3637202523E7C1309AB79E99EF2DC5827B445F4B".to_string(),
+                email_address: Some("test@doip.rocks".to_string()),
+            }
+        );
+        assert_eq!(
+            "file:__tests__/data/TEST_DOIP_PUBLIC_KEY.asc"
+                .parse::<ProfileUri>()
+                .unwrap(),
+            ProfileUri::File("__tests__/data/TEST_DOIP_PUBLIC_KEY.asc".to_string())
+        );
+        assert_eq!(
+            "https://chimbosonic.com/key.asc"
+                .parse::<ProfileUri>()
+                .unwrap(),
+            ProfileUri::Https("https://chimbosonic.com/key.asc".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_invalid_uris() {
+        for uri in ["", "hkp", "ftp:foo", "https:", "file:"] {
+            assert!(matches!(
+                uri.parse::<ProfileUri>(),
+                Err(AppError::ProfileURIMalformed { .. })
+            ));
+        }
+        for uri in [
+            "hkp:ABCD",
+            "hkp:0x",
+            "hkp:0xEF2DC5827B445F4",
+            "openpgp4fpr:EF2DC5827B445F4B",
+        ] {
+            assert!(matches!(
+                uri.parse::<ProfileUri>(),
+                Err(AppError::InvalidFingerprint { .. })
+            ));
+        }
+        for uri in [
+            "wkd:not-an-email",
+            "openpgp4fpr:3637202523E7C1309AB79E99EF2DC5827B445F4B#n=Test",
+        ] {
+            assert!(matches!(
+                uri.parse::<ProfileUri>(),
+                Err(AppError::InvalidEmailAddress { .. })
+            ));
+        }
+        assert!(matches!(
+            "aspe:keyoxide.org:toicv3".parse::<ProfileUri>(),
+            Err(AppError::InvalidAspeUri { .. })
+        ));
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for uri in [
+            "hkp:test@doip.rocks",
+            "wkd:alexis.lowe@chimbosonic.com",
+            "email:test@doip.rocks",
+            "aspe:keyoxide.org:TOICV3SYXNJP7E4P5AOK5DHW44",
+            "openpgp4fpr:3637202523E7C1309AB79E99EF2DC5827B445F4B",
+            "openpgp4fpr:3637202523E7C1309AB79E99EF2DC5827B445F4B#a=test%40doip.rocks",
+            "openpgp4fpr:3637202523E7C1309AB79E99EF2DC5827B445F4B#a=test%2Bkeys%40doip.rocks",
+        ] {
+            assert_eq!(uri.parse::<ProfileUri>().unwrap().to_string(), uri);
+        }
+    }
+
+    #[test]
+    fn openpgp4fpr_fragments() {
+        assert_eq!(
+            "openpgp4fpr:3637202523E7C1309AB79E99EF2DC5827B445F4B#a=test+keys@doip.rocks"
+                .parse::<ProfileUri>()
+                .unwrap(),
+            ProfileUri::Openpgp4fpr {
+                fingerprint: "3637202523E7C1309AB79E99EF2DC5827B445F4B".to_string(),
+                email_address: Some("test+keys@doip.rocks".to_string()),
+            }
+        );
+
+        let uri = "openpgp4fpr:3637202523E7C1309AB79E99EF2DC5827B445F4B#n=Test&a=not%20an%20email";
+        let Err(AppError::InvalidEmailAddress { span, .. }) = uri.parse::<ProfileUri>() else {
+            panic!("{uri} should be rejected");
+        };
+        assert_eq!(
+            &uri[span.offset()..span.offset() + span.len()],
+            "not%20an%20email"
+        );
+    }
+}
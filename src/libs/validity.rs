@@ -0,0 +1,154 @@
+use std::time::{Duration, SystemTime};
+
+use sequoia_openpgp::{
+    Cert,
+    cert::amalgamation::{ValidAmalgamation, ValidateAmalgamation},
+    packet::UserID,
+    policy::StandardPolicy,
+    types::RevocationStatus,
+};
+use serde::{Deserialize, Serialize};
+
+use super::error::{NotValid, warn};
+
+/// Standard policy as it stood at `time`, as of now when there is no `time`
+pub fn standard_policy(time: Option<SystemTime>) -> StandardPolicy<'static> {
+    match time {
+        Some(time) => StandardPolicy::at(time),
+        None => StandardPolicy::new(),
+    }
+}
+
+/// Whether a key or UserID may be relied on, as evaluated under sequoia's standard policy
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Validity {
+    #[default]
+    Valid,
+    Revoked,
+    Expired,
+    /// No self-signature is valid under the policy, e.g. it uses a rejected hash algorithm
+    Invalid,
+}
+
+impl Validity {
+    /// Validity of `cert` at `time`, now when there is no `time`
+    pub fn of_cert(cert: &Cert, time: Option<SystemTime>) -> Self {
+        let policy = standard_policy(time);
+        let Ok(valid_cert) = cert.with_policy(&policy, time) else {
+            return Validity::Invalid;
+        };
+        if let RevocationStatus::Revoked(_) = valid_cert.revocation_status() {
+            return Validity::Revoked;
+        }
+        match valid_cert.alive() {
+            Ok(()) => Validity::Valid,
+            Err(_) => Validity::Expired,
+        }
+    }
+
+    /// Validity of the binding of `user_id` to `cert` at `time`, now when there is no `time`
+    pub fn of_userid(cert: &Cert, user_id: &UserID, time: Option<SystemTime>) -> Self {
+        let policy = standard_policy(time);
+        let Some(userid) = cert.userids().find(|userid| userid.userid() == user_id) else {
+            return Validity::Invalid;
+        };
+        let Ok(valid_userid) = userid.clone().with_policy(&policy, time) else {
+            // Expired binding signatures are skipped when looking for the valid one, so a
+            // UserID whose self-signatures all expired has none
+            let time = time.unwrap_or_else(SystemTime::now);
+            let expired = userid.self_signatures().any(|signature| {
+                signature
+                    .signature_creation_time()
+                    .is_some_and(|creation_time| creation_time <= time)
+                    && signature.signature_alive(time, Duration::ZERO).is_err()
+            });
+            return match expired {
+                true => Validity::Expired,
+                false => Validity::Invalid,
+            };
+        };
+        match valid_userid.revocation_status() {
+            RevocationStatus::Revoked(_) => Validity::Revoked,
+            _ => Validity::Valid,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        *self == Validity::Valid
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Validity::Valid => "valid",
+            Validity::Revoked => "revoked",
+            Validity::Expired => "expired",
+            Validity::Invalid => "invalid",
+        }
+    }
+
+    /// Warns on stderr when not valid, `subject` names what was evaluated
+    pub fn warn(&self, subject: impl FnOnce() -> String) {
+        if !self.is_valid() {
+            warn(&NotValid {
+                subject: subject(),
+                state: self.name(),
+            });
+        }
+    }
+
+    /// Warning shown next to keys and UserIDs that aren't valid, empty when they are
+    pub fn flag(&self) -> &'static str {
+        match self {
+            Validity::Valid => "",
+            Validity::Revoked => " ⚠️ REVOKED",
+            Validity::Expired => " ⚠️ EXPIRED",
+            Validity::Invalid => " ⚠️ INVALID",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sequoia_openpgp::{cert::CertBuilder, parse::Parse};
+
+    #[test]
+    fn valid_cert_and_userids() {
+        let cert = Cert::from_file("__tests__/data/TEST_DOIP_PUBLIC_KEY.asc").unwrap();
+        assert_eq!(Validity::of_cert(&cert, None), Validity::Valid);
+        for userid in cert.userids() {
+            assert_eq!(
+                Validity::of_userid(&cert, userid.userid(), None),
+                Validity::Valid
+            );
+        }
+    }
+
+    #[test]
+    fn revoked_and_expired_certs() {
+        let (cert, revocation) = CertBuilder::general_purpose(None, Some("test@doip.rocks"))
+            .set_validity_period(Duration::from_secs(60))
+            .generate()
+            .unwrap();
+        let created = cert.primary_key().key().creation_time();
+        assert_eq!(Validity::of_cert(&cert, Some(created)), Validity::Valid);
+        assert_eq!(
+            Validity::of_cert(&cert, Some(created + Duration::from_secs(120))),
+            Validity::Expired
+        );
+
+        let cert = cert.insert_packets(revocation).unwrap();
+        assert_eq!(Validity::of_cert(&cert, Some(created)), Validity::Revoked);
+    }
+
+    #[test]
+    fn nothing_is_valid_before_the_key_existed() {
+        let cert = Cert::from_file("__tests__/data/TEST_DOIP_PUBLIC_KEY.asc").unwrap();
+        let before_creation = cert.primary_key().key().creation_time() - Duration::from_secs(1);
+        assert_eq!(
+            Validity::of_cert(&cert, Some(before_creation)),
+            Validity::Invalid
+        );
+    }
+}
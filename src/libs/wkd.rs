@@ -0,0 +1,261 @@
+use super::{
+    error::{WkdUserIDMismatch, warn},
+    openpgp::{cert_email_addresses, read_certs_from_bytes},
+    retry::RetryPolicy,
+};
+use bytes::Bytes;
+use reqwest::{Client, Url};
+use sequoia_openpgp::{Cert, crypto::hash::Digest, types::HashAlgorithm};
+use serde::{Deserialize, Serialize};
+
+const ZBASE32_ALPHABET: &[u8; 32] = b"ybndrfg8ejkmcpqxot1uwisza345h769";
+
+/// Where a Web Key Directory serves keys from
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WkdMethod {
+    /// `https://openpgpkey.<domain>/.well-known/openpgpkey/<domain>/hu/<hash>`
+    Advanced,
+    /// `https://<domain>/.well-known/openpgpkey/hu/<hash>`
+    Direct,
+}
+
+impl WkdMethod {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WkdMethod::Advanced => "WKD advanced",
+            WkdMethod::Direct => "WKD direct",
+        }
+    }
+
+    /// Url of the key of `email_address` for this method, `None` when it isn't an email address
+    pub fn key_url(&self, email_address: &str) -> Option<Url> {
+        let (local_part, domain) = email_address.split_once('@')?;
+        let hash = wkd_hash(local_part).ok()?;
+        let mut url = Url::parse(&format!("{}hu/{hash}", self.base_url(domain))).ok()?;
+        url.query_pairs_mut().append_pair("l", local_part);
+        Some(url)
+    }
+
+    /// Url of the policy file of the directory serving `email_address`
+    pub fn policy_url(&self, email_address: &str) -> Option<Url> {
+        let (_, domain) = email_address.split_once('@')?;
+        Url::parse(&format!("{}policy", self.base_url(domain))).ok()
+    }
+
+    fn base_url(&self, domain: &str) -> String {
+        let domain = domain.to_lowercase();
+        match self {
+            WkdMethod::Advanced => {
+                format!("https://openpgpkey.{domain}/.well-known/openpgpkey/{domain}/")
+            }
+            WkdMethod::Direct => format!("https://{domain}/.well-known/openpgpkey/"),
+        }
+    }
+}
+
+/// How the key of an address was looked up over WKD
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WkdLookup {
+    pub email_address: String,
+    /// Method the key was found with, `None` when neither method returned a key
+    pub method: Option<WkdMethod>,
+    /// Every request made, in order
+    pub requests: Vec<WkdRequest>,
+    /// Contents of the policy file of the directory the key was found in, if it serves one
+    pub policy: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub warnings: Vec<String>,
+}
+
+/// A single request made while looking up a key over WKD
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WkdRequest {
+    pub url: String,
+    /// HTTP status of the response, `None` when no response was received
+    pub status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub error: Option<String>,
+}
+
+impl WkdLookup {
+    /// Looks the key of `email_address` up using the advanced method, then the direct method
+    /// when the advanced one didn't return a key, as the WKD draft recommends. The policy file
+    /// of the directory the key was found in is fetched along with it
+    pub async fn new(email_address: &str, retry_policy: &RetryPolicy) -> (Vec<Cert>, Self) {
+        let client = Client::new();
+        let mut lookup = WkdLookup {
+            email_address: email_address.to_string(),
+            method: None,
+            requests: Vec::new(),
+            policy: None,
+            warnings: Vec::new(),
+        };
+
+        for method in [WkdMethod::Advanced, WkdMethod::Direct] {
+            let Some(url) = method.key_url(email_address) else {
+                break;
+            };
+            let Ok(key_bytes) = get(&client, url, retry_policy, &mut lookup.requests).await else {
+                continue;
+            };
+            let Ok(certs) = read_certs_from_bytes(&key_bytes, email_address) else {
+                continue;
+            };
+
+            lookup.method = Some(method);
+            if let Some(url) = method.policy_url(email_address) {
+                lookup.policy = get(&client, url, retry_policy, &mut lookup.requests)
+                    .await
+                    .ok()
+                    .map(|policy| String::from_utf8_lossy(&policy).into_owned());
+            }
+            return (certs, lookup);
+        }
+        (Vec::new(), lookup)
+    }
+
+    /// The lookup as reported for `cert`, warning when none of its UserIDs holds the address
+    pub fn for_cert(&self, cert: &Cert) -> Self {
+        let mut lookup = self.clone();
+        let holds_address = cert_email_addresses(cert)
+            .iter()
+            .any(|email_address| email_address.eq_ignore_ascii_case(&self.email_address));
+        if !holds_address {
+            let mismatch = WkdUserIDMismatch {
+                fingerprint: cert.fingerprint().to_hex(),
+                email_address: self.email_address.clone(),
+            };
+            warn(&mismatch);
+            lookup.warnings.push(mismatch.to_string());
+        }
+        lookup
+    }
+
+    /// Every request made, as `<url>: <status>` lines for error messages
+    pub fn requests_summary(&self) -> String {
+        self.requests
+            .iter()
+            .map(WkdRequest::to_string)
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+impl std::fmt::Display for WkdRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.status, &self.error) {
+            (Some(status), _) => write!(f, "GET {}: {status}", self.url),
+            (None, Some(error)) => write!(f, "GET {}: {error}", self.url),
+            (None, None) => write!(f, "GET {}", self.url),
+        }
+    }
+}
+
+/// GETs `url`, recording the request and the status it got in `requests`
+async fn get(
+    client: &Client,
+    url: Url,
+    retry_policy: &RetryPolicy,
+    requests: &mut Vec<WkdRequest>,
+) -> reqwest::Result<Bytes> {
+    let mut request = WkdRequest {
+        url: url.to_string(),
+        status: None,
+        error: None,
+    };
+    let result = match retry_policy.send(|| client.get(url.clone())).await {
+        Ok(response) => {
+            request.status = Some(response.status().as_u16());
+            match response.error_for_status() {
+                Ok(response) => response.bytes().await,
+                Err(error) => Err(error),
+            }
+        }
+        Err(error) => Err(error),
+    };
+    request.error = result
+        .as_ref()
+        .err()
+        .filter(|error| error.status().is_none())
+        .map(ToString::to_string);
+    requests.push(request);
+    result
+}
+
+/// z-base-32 encoded SHA-1 of the lowercased local part, as WKD names key files
+fn wkd_hash(local_part: &str) -> sequoia_openpgp::Result<String> {
+    let mut context = HashAlgorithm::SHA1.context()?;
+    context.update(local_part.to_lowercase().as_bytes());
+    Ok(base32(ZBASE32_ALPHABET, &context.into_digest()?))
+}
+
+/// Unpadded base32 of `bytes` using `alphabet`
+fn base32(alphabet: &[u8; 32], bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | u32::from(*byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(alphabet[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(alphabet[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sequoia_openpgp::parse::Parse;
+
+    #[test]
+    fn wkd_key_urls() {
+        assert_eq!(
+            WkdMethod::Advanced
+                .key_url("Joe.Doe@Example.ORG")
+                .unwrap()
+                .as_str(),
+            "https://openpgpkey.example.org/.well-known/openpgpkey/example.org/hu/iy9q119eutrkn8s1mk4r39qejnbu3n5q?l=Joe.Doe"
+        );
+        assert_eq!(
+            WkdMethod::Direct
+                .key_url("Joe.Doe@Example.ORG")
+                .unwrap()
+                .as_str(),
+            "https://example.org/.well-known/openpgpkey/hu/iy9q119eutrkn8s1mk4r39qejnbu3n5q?l=Joe.Doe"
+        );
+        assert_eq!(
+            WkdMethod::Advanced
+                .policy_url("Joe.Doe@Example.ORG")
+                .unwrap()
+                .as_str(),
+            "https://openpgpkey.example.org/.well-known/openpgpkey/example.org/policy"
+        );
+        assert!(WkdMethod::Direct.key_url("not-an-email").is_none());
+    }
+
+    #[test]
+    fn warn_when_no_userid_holds_the_address() {
+        let cert = Cert::from_file("__tests__/data/TEST_DOIP_PUBLIC_KEY.asc").unwrap();
+        let lookup = WkdLookup {
+            email_address: "test@doip.rocks".to_string(),
+            method: Some(WkdMethod::Direct),
+            requests: Vec::new(),
+            policy: None,
+            warnings: Vec::new(),
+        };
+        assert!(lookup.for_cert(&cert).warnings.is_empty());
+
+        let lookup = WkdLookup {
+            email_address: "someone.else@doip.rocks".to_string(),
+            ..lookup
+        };
+        assert_eq!(lookup.for_cert(&cert).warnings.len(), 1);
+    }
+}
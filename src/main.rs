@@ -1,54 +1,222 @@
-use clap::Parser;
 use miette::Result;
-use std::env;
+use std::{env, process::ExitCode};
 
-mod libs;
-use libs::aspe::AspProfile;
-use libs::clap::Args;
-use libs::doip::Profile;
-use libs::error::AppError;
-use libs::openpgp::KeyProfile;
+use futures::StreamExt;
+use keyoxide_cli_rs::libs::aspe::AspProfile;
+use keyoxide_cli_rs::libs::batch::{Batch, combine_exit_statuses, verify_batch};
+use keyoxide_cli_rs::libs::claim::ClaimVerification;
+use keyoxide_cli_rs::libs::clap::{
+    Args, AspeArgs, AspeCommand, CacheCommand, ClaimArgs, Command, FetchArgs, GlobalArgs, KeyArgs,
+    VerifyArgs,
+};
+use keyoxide_cli_rs::libs::config::Config;
+use keyoxide_cli_rs::libs::doip::{PrintFormat, Printable};
+use keyoxide_cli_rs::libs::error::{AppError, DeprecatedInvocation, warn};
+use keyoxide_cli_rs::libs::inspect::CertInspection;
+use keyoxide_cli_rs::libs::limit::run_until;
+use keyoxide_cli_rs::libs::openpgp::{armor_cert, fetch_certs};
+use keyoxide_cli_rs::libs::policy::ExitStatus;
+use keyoxide_cli_rs::libs::profile::{VerifyOptions, verify_profile_uri};
+use keyoxide_cli_rs::libs::uri::ProfileUri;
+use sequoia_openpgp::Cert;
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
+fn main() -> ExitCode {
+    let args = match Args::parse_with_config() {
+        Ok(args) => args,
+        Err(error) => {
+            let report = miette::Report::from(error);
+            eprintln!("Error: {report:?}");
+            return ExitStatus::from_error(&report).into();
+        }
+    };
 
-    if args.quiet {
+    // The environment is only safe to change while no other thread reads it, so before the
+    // runtime starts its workers. doip builds its own HTTP clients, which pick the proxy up
+    // from there
+    if args.global.quiet {
         unsafe { env::set_var("RUST_LOG", "off") };
     }
+    if let Some(proxy) = &args.global.proxy {
+        unsafe {
+            env::set_var("HTTPS_PROXY", proxy);
+            env::set_var("HTTP_PROXY", proxy);
+        };
+    }
 
-    if let Some(doip_profile_uri) = args.doip_profile_uri {
-        return match &doip_profile_uri[..5] {
-            "hkps:" | "hkp:" => {
-                let key_profiles =
-                    KeyProfile::new_from_hkp(doip_profile_uri, args.keyserver_domain).await?;
-                for key_profile in key_profiles {
-                    key_profile.print(&args.print_format);
-                }
-                Ok(())
-            }
-            "wkd:" => {
-                let key_profiles = KeyProfile::new_from_wkd(doip_profile_uri).await?;
-                for key_profile in key_profiles {
-                    key_profile.print(&args.print_format);
-                }
-                Ok(())
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Failed building the Runtime");
+    match runtime.block_on(run(args)) {
+        Ok(exit_status) => exit_status.into(),
+        Err(report) => {
+            eprintln!("Error: {report:?}");
+            ExitStatus::from_error(&report).into()
+        }
+    }
+}
+
+async fn run(args: Args) -> Result<ExitStatus> {
+    let command = match args.command {
+        Some(_) if args.legacy.is_used() => return Err(AppError::LegacyArgsWithSubcommand.into()),
+        Some(command) => command,
+        None => {
+            if args.legacy.is_used() {
+                warn(&DeprecatedInvocation);
             }
-            "aspe:" => {
-                let asp_profile = AspProfile::new(&doip_profile_uri, args.skip_verify_ssl).await?;
-                asp_profile.print(&args.print_format);
-                Ok(())
+            Command::Verify(args.legacy.into_verify_args())
+        }
+    };
+
+    match command {
+        Command::Verify(verify_args) => verify(verify_args, &args.global, &args.config).await,
+        Command::Fetch(fetch_args) => fetch(fetch_args, &args.global, &args.config).await,
+        Command::Inspect(key_args) => inspect(key_args, &args.global, &args.config).await,
+        Command::Claim(claim_args) => claim(claim_args, &args.global).await,
+        Command::Aspe(AspeCommand::Verify(aspe_args)) => aspe_verify(aspe_args, &args.global).await,
+        Command::Aspe(AspeCommand::Fetch(aspe_args)) => aspe_fetch(aspe_args, &args.global).await,
+        Command::Cache(cache_command) => cache(cache_command, &args.global),
+    }
+}
+
+async fn verify(
+    verify_args: VerifyArgs,
+    global: &GlobalArgs,
+    config: &Config,
+) -> Result<ExitStatus> {
+    let mut profile_uris = verify_args.profile_uris()?;
+    let options = VerifyOptions {
+        keyservers: global.keyservers(&verify_args.keyserver_domain, config),
+        skip_verify_ssl: global.skip_verify_ssl,
+        context: global.context(),
+    };
+
+    if profile_uris.len() == 1 && verify_args.batch.is_none() {
+        let profile_uri: ProfileUri = profile_uris.remove(0).parse()?;
+        let profile = verify_profile_uri(&profile_uri, &options).await?;
+        profile.print(&global.print_format);
+        return Ok(ExitStatus::evaluate(profile.proofs(), &global.require));
+    }
+
+    let mut entries = std::pin::pin!(verify_batch(profile_uris, &options, verify_args.jobs));
+    match global.print_format {
+        PrintFormat::Json | PrintFormat::JsonPretty => {
+            let batch = Batch {
+                entries: entries.collect().await,
+            };
+            batch.print(&global.print_format);
+            Ok(batch.exit_status(&global.require))
+        }
+        PrintFormat::Ndjson | PrintFormat::Text => {
+            let mut exit_statuses = Vec::new();
+            while let Some(entry) = entries.next().await {
+                entry.print(&global.print_format);
+                exit_statuses.push(entry.exit_status(&global.require));
             }
-            _ => Err(AppError::ProfileURIMalformed.into()),
-        };
+            Ok(combine_exit_statuses(&exit_statuses))
+        }
+    }
+}
+
+async fn fetch(fetch_args: FetchArgs, global: &GlobalArgs, config: &Config) -> Result<ExitStatus> {
+    let profile_uri: ProfileUri = fetch_args.doip_profile_uri.parse()?;
+    let context = global.context();
+    let certs = run_until(
+        context.limiter.deadline(),
+        fetch_certs(
+            &profile_uri,
+            &global.keyservers(&fetch_args.keyserver_domain, config),
+            &context.retry_policy,
+            &context.cache,
+        ),
+    )
+    .await
+    .ok_or(AppError::TotalTimeoutElapsed)??;
+    for fetched_cert in certs {
+        print!("{}", armor_cert(&fetched_cert.cert)?);
     }
+    Ok(ExitStatus::Verified)
+}
+
+async fn inspect(key_args: KeyArgs, global: &GlobalArgs, config: &Config) -> Result<ExitStatus> {
+    let profile_uri = key_args.profile_uri()?;
+    let context = global.context();
+    let certs = run_until(
+        context.limiter.deadline(),
+        fetch_certs(
+            &profile_uri,
+            &global.keyservers(&key_args.keyserver_domain, config),
+            &context.retry_policy,
+            &context.cache,
+        ),
+    )
+    .await
+    .ok_or(AppError::TotalTimeoutElapsed)??;
+    let issuers: Vec<Cert> = certs
+        .iter()
+        .map(|fetched_cert| fetched_cert.cert.clone())
+        .collect();
+    for fetched_cert in &certs {
+        CertInspection::new(&fetched_cert.cert, &issuers, global.reference_time())?
+            .print(&global.print_format);
+    }
+    Ok(ExitStatus::Verified)
+}
 
-    match args.input_key_file {
-        Some(key_path) => {
-            let key_profile = KeyProfile::new_from_file(key_path).await?;
-            key_profile.print(&args.print_format);
-            Ok(())
+async fn claim(claim_args: ClaimArgs, global: &GlobalArgs) -> Result<ExitStatus> {
+    let claim_verification = ClaimVerification::new(
+        &claim_args.service_uri,
+        &claim_args.identity_uri,
+        &global.context(),
+    )
+    .await;
+    claim_verification.print(&global.print_format);
+    match claim_verification.is_verified() {
+        true => Ok(ExitStatus::Verified),
+        false => Ok(ExitStatus::NoneVerified),
+    }
+}
+
+async fn aspe_verify(aspe_args: AspeArgs, global: &GlobalArgs) -> Result<ExitStatus> {
+    let aspe_uri = aspe_args.aspe_uri()?;
+    let asp_profile = AspProfile::new(
+        &aspe_uri.to_string(),
+        global.skip_verify_ssl,
+        &global.context(),
+    )
+    .await?;
+    asp_profile.print(&global.print_format);
+    Ok(ExitStatus::evaluate(asp_profile.proofs(), &global.require))
+}
+
+async fn aspe_fetch(aspe_args: AspeArgs, global: &GlobalArgs) -> Result<ExitStatus> {
+    let aspe_uri = aspe_args.aspe_uri()?;
+    let context = global.context();
+    let jws = run_until(
+        context.limiter.deadline(),
+        AspProfile::fetch_jws(
+            &aspe_uri.to_string(),
+            global.skip_verify_ssl,
+            &context.retry_policy,
+            &context.cache,
+        ),
+    )
+    .await
+    .ok_or(AppError::TotalTimeoutElapsed)??;
+    println!("{jws}");
+    Ok(ExitStatus::Verified)
+}
+
+fn cache(cache_command: CacheCommand, global: &GlobalArgs) -> Result<ExitStatus> {
+    let cache = global.cache();
+    match cache_command {
+        CacheCommand::List => {
+            for entry in cache.list()? {
+                entry.print(&global.print_format);
+            }
         }
-        None => Err(AppError::ProfileNotProvided.into()),
+        CacheCommand::Clear => println!("Removed {} cache entries", cache.clear()?),
+        CacheCommand::Prune => println!("Removed {} expired cache entries", cache.prune()?),
     }
+    Ok(ExitStatus::Verified)
 }